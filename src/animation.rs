@@ -0,0 +1,350 @@
+//! Animated GIF, WebP and PNG (APNG) images, which are transformed frame by frame.
+
+use crate::{
+    error::ApiError,
+    utils::{decode_error, decode_with_limits, inspect, DecodeLimits},
+};
+use axum::body::Bytes;
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
+    AnimationDecoder, Frame, Frames, ImageFormat, RgbaImage,
+};
+use std::io::Cursor;
+
+/// The frames of an image, a still image has exactly one.
+///
+/// Every frame has the full size of the image, so frames can be transformed independently of each other.
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    /// How often the animation is played, `0` means forever.
+    pub plays: u32,
+}
+
+impl Animation {
+    pub fn still(img: RgbaImage) -> Self {
+        Self {
+            frames: vec![Frame::new(img)],
+            plays: 1,
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.frames.len() > 1
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.frames[0].buffer().dimensions()
+    }
+
+    /// Applies `transform` to every frame, keeping the delays.
+    pub fn try_map(
+        self,
+        mut transform: impl FnMut(RgbaImage) -> Result<RgbaImage, ApiError>,
+    ) -> Result<Self, ApiError> {
+        let frames = self
+            .frames
+            .into_iter()
+            .map(|frame| {
+                let delay = frame.delay();
+                Ok(Frame::from_parts(
+                    transform(frame.into_buffer())?,
+                    0,
+                    0,
+                    delay,
+                ))
+            })
+            .collect::<Result<_, ApiError>>()?;
+
+        Ok(Self {
+            frames,
+            plays: self.plays,
+        })
+    }
+}
+
+/// Decodes every frame of an image with the configured [`DecodeLimits`].
+pub fn animation_from_bytes(bytes: Bytes) -> Result<Animation, ApiError> {
+    decode_animation_with_limits(bytes, DecodeLimits::from_config())
+}
+
+/// Decodes every frame of an animated GIF, WebP or PNG, any other image is decoded as a still image.
+pub fn decode_animation_with_limits(
+    bytes: Bytes,
+    limits: DecodeLimits,
+) -> Result<Animation, ApiError> {
+    let (format, (width, height)) = inspect(&bytes, limits)?;
+    let Some(info) = animation_info(&bytes, format) else {
+        return decode_with_limits(bytes, limits).map(Animation::still);
+    };
+
+    let frame_pixels = width as u64 * height as u64;
+    let check_frames = |count: u64| limits.check_animation(count, frame_pixels);
+    // Some formats declare their frame count up front, which saves decoding frames that would be thrown away.
+    if let Some(declared) = info.frames {
+        check_frames(declared as u64)?;
+    }
+
+    let decode_error = decode_error(format);
+    let cursor = Cursor::new(&bytes[..]);
+    let frames: Frames = match format {
+        ImageFormat::Gif => GifDecoder::with_limits(cursor, limits.decoder_limits())
+            .map_err(&decode_error)?
+            .into_frames(),
+        ImageFormat::Png => PngDecoder::with_limits(cursor, limits.decoder_limits())
+            .map_err(&decode_error)?
+            .apng()
+            .into_frames(),
+        ImageFormat::WebP => WebPDecoder::new(cursor)
+            .map_err(&decode_error)?
+            .into_frames(),
+        _ => unreachable!("only GIF, PNG and WebP can be animated"),
+    };
+
+    let mut decoded = Vec::new();
+    for frame in frames {
+        check_frames(decoded.len() as u64 + 1)?;
+        decoded.push(frame.map_err(&decode_error)?);
+    }
+
+    match decoded.len() {
+        0 => decode_with_limits(bytes, limits).map(Animation::still),
+        _ => Ok(Animation {
+            frames: decoded,
+            plays: info.plays,
+        }),
+    }
+}
+
+/// What an animated image declares about itself.
+struct AnimationInfo {
+    /// The number of frames, if the format stores it
+    frames: Option<u32>,
+    /// How often the animation is played, `0` means forever
+    plays: u32,
+}
+
+/// Reads the animation parameters, `None` if the image isn't animated.
+fn animation_info(bytes: &[u8], format: ImageFormat) -> Option<AnimationInfo> {
+    match format {
+        ImageFormat::Gif => Some(gif_info(bytes)),
+        ImageFormat::Png => apng_info(bytes),
+        ImageFormat::WebP => webp_info(bytes),
+        _ => None,
+    }
+}
+
+/// GIFs loop through the NETSCAPE2.0 application extension, which counts the repetitions after the first play.
+fn gif_info(bytes: &[u8]) -> AnimationInfo {
+    const NETSCAPE: &[u8] = b"NETSCAPE2.0\x03\x01";
+
+    let repetitions = bytes
+        .windows(NETSCAPE.len() + 2)
+        .find(|window| window.starts_with(NETSCAPE))
+        .map(|window| u16::from_le_bytes([window[NETSCAPE.len()], window[NETSCAPE.len() + 1]]));
+
+    AnimationInfo {
+        frames: None,
+        plays: match repetitions {
+            None => 1,
+            Some(0) => 0,
+            Some(repetitions) => repetitions as u32 + 1,
+        },
+    }
+}
+
+/// APNGs declare their frames and plays in the `acTL` chunk, which comes before the image data.
+fn apng_info(bytes: &[u8]) -> Option<AnimationInfo> {
+    let mut position = 8;
+
+    while let Some(header) = bytes.get(position..position + 8) {
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let data = bytes.get(position + 8..(position + 8).checked_add(length)?)?;
+
+        match &header[4..] {
+            b"acTL" if data.len() >= 8 => {
+                return Some(AnimationInfo {
+                    frames: Some(u32::from_be_bytes(data[..4].try_into().unwrap())),
+                    plays: u32::from_be_bytes(data[4..8].try_into().unwrap()),
+                })
+            }
+            b"IDAT" => return None,
+            _ => position += 12 + length,
+        }
+    }
+
+    None
+}
+
+/// Animated WebPs store their loop count in the `ANIM` chunk and every frame in an `ANMF` chunk.
+fn webp_info(bytes: &[u8]) -> Option<AnimationInfo> {
+    let mut position = 12;
+    let mut plays = None;
+    let mut frames = 0;
+
+    while let Some(header) = bytes.get(position..position + 8) {
+        let length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let data = bytes.get(position + 8..(position + 8).checked_add(length)?);
+
+        match (&header[..4], data) {
+            (b"ANIM", Some(data)) if data.len() >= 6 => {
+                plays = Some(u16::from_le_bytes([data[4], data[5]]) as u32)
+            }
+            (b"ANMF", _) => frames += 1,
+            _ => {}
+        }
+
+        // Chunks are padded to an even length.
+        position += 8 + length + (length & 1);
+    }
+
+    plays.map(|plays| AnimationInfo {
+        frames: Some(frames),
+        plays,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::gif::GifEncoder, codecs::gif::Repeat, Delay, Rgba};
+
+    const LIMITS: DecodeLimits = DecodeLimits {
+        max_width: 1024,
+        max_height: 1024,
+        max_pixels: 512 * 512,
+        max_alloc: 16 * 1024 * 1024,
+        max_frames: 8,
+        max_animation_pixels: 64 * 64 * 8,
+    };
+
+    fn frame(shade: u8) -> RgbaImage {
+        RgbaImage::from_pixel(64, 64, Rgba([shade, 0, 255 - shade, 255]))
+    }
+
+    fn gif(frames: u8, repeat: Option<Repeat>) -> Bytes {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            if let Some(repeat) = repeat {
+                encoder.set_repeat(repeat).unwrap();
+            }
+            for index in 0..frames {
+                let delay = Delay::from_numer_denom_ms(40 + index as u32 * 10, 1);
+                encoder
+                    .encode_frame(Frame::from_parts(frame(index * 20), 0, 0, delay))
+                    .unwrap();
+            }
+        }
+        bytes.into()
+    }
+
+    fn apng(frames: u8, plays: u32) -> Bytes {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, 64, 64);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_animated(frames as u32, plays).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            for index in 0..frames {
+                writer.set_frame_delay(1, 10).unwrap();
+                writer.write_image_data(&frame(index * 20)).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        bytes.into()
+    }
+
+    fn delays_ms(animation: &Animation) -> Vec<u32> {
+        animation
+            .frames
+            .iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                numer / denom
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_every_gif_frame() {
+        let animation = decode_animation_with_limits(gif(3, None), LIMITS).unwrap();
+
+        assert_eq!(animation.frames.len(), 3);
+        assert_eq!(delays_ms(&animation), [40, 50, 60]);
+        assert_eq!(animation.frames[2].buffer().get_pixel(0, 0).0[0], 40);
+        assert_eq!(animation.plays, 1);
+    }
+
+    #[test]
+    fn reads_gif_loop_counts() {
+        let plays = |repeat| {
+            decode_animation_with_limits(gif(2, Some(repeat)), LIMITS)
+                .unwrap()
+                .plays
+        };
+
+        assert_eq!(plays(Repeat::Infinite), 0);
+        assert_eq!(plays(Repeat::Finite(2)), 3);
+    }
+
+    #[test]
+    fn decodes_every_apng_frame() {
+        let animation = decode_animation_with_limits(apng(4, 2), LIMITS).unwrap();
+
+        assert_eq!(animation.frames.len(), 4);
+        assert_eq!(delays_ms(&animation), [100; 4]);
+        assert_eq!(animation.plays, 2);
+    }
+
+    #[test]
+    fn decodes_still_images_as_one_frame() {
+        let mut png = Vec::new();
+        frame(0)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let animation = decode_animation_with_limits(png.into(), LIMITS).unwrap();
+
+        assert!(!animation.is_animated());
+        assert_eq!(animation.dimensions(), (64, 64));
+    }
+
+    #[test]
+    fn rejects_too_many_frames() {
+        let gif = decode_animation_with_limits(gif(9, None), LIMITS);
+        assert!(matches!(gif, Err(ApiError::ImageTooLarge(_))));
+
+        // rejected by the frame count in its header, before any frame is decoded
+        let apng = decode_animation_with_limits(apng(9, 0), LIMITS);
+        assert!(matches!(apng, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_too_many_pixels_over_all_frames() {
+        let limits = DecodeLimits {
+            max_animation_pixels: 64 * 64 * 2,
+            ..LIMITS
+        };
+
+        let result = decode_animation_with_limits(gif(3, None), limits);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn keeps_delays_when_transforming_frames() {
+        let animation = decode_animation_with_limits(gif(3, Some(Repeat::Infinite)), LIMITS)
+            .unwrap()
+            .try_map(|mut img| {
+                img.put_pixel(0, 0, Rgba([0; 4]));
+                Ok(img)
+            })
+            .unwrap();
+
+        assert_eq!(delays_ms(&animation), [40, 50, 60]);
+        assert_eq!(animation.plays, 0);
+        assert!(animation
+            .frames
+            .iter()
+            .all(|frame| frame.buffer().get_pixel(0, 0).0[3] == 0));
+    }
+}
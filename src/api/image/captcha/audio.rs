@@ -0,0 +1,139 @@
+//! Reads captchas out loud for everyone who can't see the image.
+
+use super::speech::{self, Voice, SAMPLE_RATE};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// The characters mumbled in the background, they are played backwards so they can't be mistaken for the answer.
+const BABBLE: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// The loudest the spoken characters get, the rest is left for the noise.
+const SPEECH_PEAK: f32 = 0.7;
+
+/// Spells out `text` as a 16 bit mono WAV file, whitespace is skipped. Returns `None` if [`speech::can_spell`]
+/// rejects the text.
+///
+/// `noise` goes from `0.0` (clean) to `1.0`, and controls the background noise, the mumbling and how
+/// much the voice varies. The same `seed` always gives the same clip, so downloading a captcha again
+/// doesn't help with filtering out the noise.
+pub fn render_wav(text: &str, announce_case: bool, noise: f32, seed: u64) -> Option<Vec<u8>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let ms = |ms: f32| (ms * SAMPLE_RATE as f32 / 1000.0) as usize;
+
+    let base_pitch = rng.gen_range(0.9..1.15);
+    let mut samples = vec![0.0; ms(400.0)];
+    for character in text.chars().filter(|character| !character.is_whitespace()) {
+        let voice = Voice {
+            pitch: base_pitch * (1.0 + rng.gen_range(-0.15..0.15) * noise),
+            tempo: rng.gen_range(0.9..1.1),
+        };
+        samples.extend(speech::speak(character, announce_case, voice, &mut rng)?);
+        samples.resize(samples.len() + ms(rng.gen_range(450.0..750.0)), 0.0);
+    }
+    normalize(&mut samples, SPEECH_PEAK);
+
+    if noise > 0.0 {
+        for _ in 0..text.chars().count() * 2 {
+            let character = BABBLE[rng.gen_range(0..BABBLE.len())] as char;
+            let voice = Voice {
+                pitch: rng.gen_range(0.8..1.4),
+                tempo: rng.gen_range(0.8..1.2),
+            };
+            let mut mumble = speech::speak(character, false, voice, &mut rng)?;
+            mumble.reverse();
+            normalize(&mut mumble, SPEECH_PEAK * 0.45 * noise);
+
+            let start = rng.gen_range(0..samples.len().saturating_sub(mumble.len()).max(1));
+            for (sample, mumbled) in samples[start..].iter_mut().zip(mumble) {
+                *sample += mumbled;
+            }
+        }
+
+        // Mostly low rumble with some hiss, slowly getting louder and quieter.
+        let mut rumble = 0.0;
+        let wobble = rng.gen_range(0.5..1.5);
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let white = rng.gen_range(-1.0f32..1.0);
+            rumble = rumble * 0.97 + white * 0.1;
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let swell = 0.75 + 0.25 * (t * wobble * std::f32::consts::TAU).sin();
+            *sample += (rumble * 0.8 + white * 0.15) * 0.3 * noise * swell;
+        }
+    }
+
+    Some(wav(&samples))
+}
+
+fn normalize(samples: &mut [f32], peak: f32) {
+    let max = samples
+        .iter()
+        .fold(0.0f32, |max, sample| max.max(sample.abs()));
+    if max > 0.0 {
+        samples.iter_mut().for_each(|sample| *sample *= peak / max);
+    }
+}
+
+/// Encodes the samples as a PCM WAV file.
+fn wav(samples: &[f32]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes()); // mono
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+
+    for sample in samples {
+        let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(wav: &[u8], index: usize) -> u32 {
+        u32::from_le_bytes(wav[index..index + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn writes_a_wav_header() {
+        let wav = render_wav("a1", true, 0.5, 7).unwrap();
+
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+        assert_eq!(u32_at(&wav, 24), SAMPLE_RATE);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40) as usize, wav.len() - 44);
+        assert_eq!(wav.len() % 2, 0);
+    }
+
+    #[test]
+    fn gives_the_same_clip_for_a_seed() {
+        assert_eq!(
+            render_wav("Ab3", true, 0.7, 42),
+            render_wav("Ab3", true, 0.7, 42)
+        );
+        assert_ne!(
+            render_wav("Ab3", true, 0.7, 42),
+            render_wav("Ab3", true, 0.7, 43)
+        );
+    }
+
+    #[test]
+    fn mumbles_only_spellable_characters() {
+        assert!(speech::can_spell(std::str::from_utf8(BABBLE).unwrap()));
+        assert!(render_wav("a€", false, 0.0, 0).is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::error::ApiError;
+use axum::http::StatusCode;
+use rand::Rng;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// Characters that are easily mistaken for each other.
+const CONFUSABLES: &str = "01IOlo";
+
+const DIGITS: &str = "0123456789";
+const LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const ALPHANUMERIC: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// The most characters a custom character set can have.
+const MAX_CUSTOM_CHARACTERS: usize = 128;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaCharset {
+    #[default]
+    Alphanumeric,
+    Letters,
+    Digits,
+    /// The characters given in `characters`
+    Custom,
+}
+
+impl CaptchaCharset {
+    /// The characters a random captcha text is made of.
+    pub fn characters(
+        self,
+        custom: Option<&str>,
+        exclude_confusables: bool,
+    ) -> Result<Vec<char>, ApiError> {
+        let characters = match (self, custom) {
+            (Self::Custom, Some(custom)) => custom,
+            (Self::Custom, None) => {
+                return Err(ApiError::AnyStatic(
+                    StatusCode::BAD_REQUEST,
+                    "`characters` is required for the `custom` charset.",
+                ))
+            }
+            (_, Some(_)) => {
+                return Err(ApiError::AnyStatic(
+                    StatusCode::BAD_REQUEST,
+                    "`characters` can only be used with the `custom` charset.",
+                ))
+            }
+            (Self::Alphanumeric, None) => ALPHANUMERIC,
+            (Self::Letters, None) => LETTERS,
+            (Self::Digits, None) => DIGITS,
+        };
+
+        if characters.chars().count() > MAX_CUSTOM_CHARACTERS
+            || characters
+                .chars()
+                .any(|character| character.is_whitespace() || character.is_control())
+        {
+            return Err(ApiError::Any(
+                StatusCode::BAD_REQUEST,
+                format!("`characters` must be at most {MAX_CUSTOM_CHARACTERS} characters without whitespace."),
+            ));
+        }
+
+        let mut characters: Vec<char> = characters
+            .chars()
+            .filter(|character| !exclude_confusables || !CONFUSABLES.contains(*character))
+            .collect();
+        characters.sort_unstable();
+        characters.dedup();
+
+        if characters.len() < 2 {
+            return Err(ApiError::AnyStatic(
+                StatusCode::BAD_REQUEST,
+                "The charset needs at least 2 different characters.",
+            ));
+        }
+
+        Ok(characters)
+    }
+}
+
+/// A random text of `length` characters.
+pub fn random_text(characters: &[char], length: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..length)
+        .map(|_| characters[rng.gen_range(0..characters.len())])
+        .collect()
+}
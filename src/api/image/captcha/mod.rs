@@ -1,53 +1,86 @@
+mod audio;
+mod charset;
+mod mode;
+mod render;
+mod speech;
+mod words;
+
+use super::{
+    hex_color::HexColor,
+    output::{EncodedImage, ImageOutput, OutputQueryParams},
+};
 use crate::{
+    captcha_store::{Challenge, Verification},
+    config::CONFIG,
     error::ApiError,
-    extract::{Json, Query},
+    extract::{Json, Path, Query},
+    state::AppState,
     ApiResult,
 };
 use axum::{
+    extract::State,
     http::{header, StatusCode},
-    response::AppendHeaders,
+    response::IntoResponse,
 };
-use captcha_rs::CaptchaBuilder;
-use image::ImageFormat;
+pub use charset::CaptchaCharset;
+use image::{DynamicImage, RgbImage};
+pub use mode::CaptchaMode;
+use render::{CaptchaStyle, MIN_HEIGHT};
 use serde::{Deserialize, Serialize};
-use serde_default_utils::default_u32;
-use std::io::Cursor;
+use serde_default_utils::{default_bool, default_u32, default_usize};
+use std::sync::Arc;
 use utoipa::{IntoParams, ToSchema};
 
-mod defaults {
-    use rand::Rng;
-
-    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ\
-    abcdefghijkmnpqrstuvwxyz\
-    23456789";
-
-    pub fn captcha_text() -> String {
-        let mut rng = rand::thread_rng();
-        (0..5)
-            .map(|_| {
-                let idx = rng.gen_range(0..CHARSET.len());
-                CHARSET[idx] as char
-            })
-            .collect()
-    }
-}
+/// The longest text a captcha can have.
+const MAX_TEXT_LENGTH: usize = 16;
+const MIN_WIDTH: u32 = 40;
+const MAX_WIDTH: u32 = 1024;
+const MAX_HEIGHT: u32 = 512;
 
-#[derive(Debug, PartialEq, PartialOrd, Deserialize, IntoParams)]
+#[derive(Debug, Deserialize, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct CaptchaQueryParams {
     #[serde(default = "default_u32::<5>")]
     #[param(minimum = 1, maximum = 10, default = 5)]
     pub difficulty: u32,
 
-    #[serde(default = "defaults::captcha_text")]
-    #[param(required = false, min_length = 1, max_length = 5)]
-    /// Defaults to a random string of length 5
-    pub text: String,
+    #[serde(default)]
+    #[param(inline)]
+    /// What the captcha asks for, `length`, `charset` and `characters` only apply to the `text` mode
+    pub mode: CaptchaMode,
+
+    /// Rejected in every mode, a challenge whose answer the caller chose would prove nothing. `/image/gen_captcha`
+    /// renders a given text without storing it.
+    pub text: Option<String>,
+
+    #[serde(default = "default_usize::<5>")]
+    #[param(minimum = 1, maximum = 16, default = 5)]
+    /// The length of the random text
+    pub length: usize,
+
+    #[serde(default)]
+    #[param(inline)]
+    /// The characters of the random text
+    pub charset: CaptchaCharset,
+
+    /// The characters of the `custom` charset, e.g. `ABC123`
+    pub characters: Option<String>,
+
+    #[serde(default = "default_bool::<true>")]
+    #[serde(rename = "excludeConfusables")]
+    #[param(default = true)]
+    /// Leaves out characters that are easily mistaken for each other (`0`, `O`, `o`, `1`, `I` and `l`)
+    pub exclude_confusables: bool,
 
     #[serde(default)]
     #[serde(rename = "darkMode")]
     #[param(default = false)]
     pub dark_mode: bool,
+
+    #[serde(rename = "audioNoise")]
+    #[param(minimum = 0.0, maximum = 1.0)]
+    /// How noisy the audio version is, from `0` (clean) to `1`, defaults to half the difficulty divided by 10
+    pub audio_noise: Option<f32>,
 }
 
 #[derive(Debug, PartialEq, PartialOrd, Deserialize, IntoParams)]
@@ -56,108 +89,320 @@ pub struct GenCaptchaQueryParams {
     #[param(minimum = 1, maximum = 10)]
     pub difficulty: u32,
 
-    #[param(required = true, min_length = 1, max_length = 5)]
+    #[param(required = true, min_length = 1, max_length = 16)]
     pub text: String,
 
     #[serde(rename = "darkMode")]
     pub dark_mode: bool,
 }
 
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CaptchaStyleQueryParams {
+    #[serde(default = "default_u32::<160>")]
+    #[param(minimum = 40, maximum = 1024, default = 160)]
+    /// The width of the image, at least 10 pixels plus 30 per character for up to 3 characters, 24 for up to 5 and 18
+    /// for more
+    pub width: u32,
+
+    #[serde(default = "default_u32::<40>")]
+    #[param(minimum = 40, maximum = 512, default = 40)]
+    pub height: u32,
+
+    #[param(value_type = Option<String>)]
+    /// The color of the text, lines and circles, defaults to random colors (the alpha is ignored)
+    pub foreground: Option<HexColor>,
+
+    #[param(value_type = Option<String>)]
+    /// The background color, defaults to light blue or, in dark mode, almost black (the alpha is ignored)
+    pub background: Option<HexColor>,
+}
+
+impl CaptchaStyleQueryParams {
+    fn into_style(
+        self,
+        text: &str,
+        difficulty: u32,
+        dark_mode: bool,
+    ) -> Result<CaptchaStyle, ApiError> {
+        if !(MIN_WIDTH..=MAX_WIDTH).contains(&self.width)
+            || !(MIN_HEIGHT..=MAX_HEIGHT).contains(&self.height)
+        {
+            return Err(ApiError::Any(
+                StatusCode::BAD_REQUEST,
+                format!("The captcha must be between {MIN_WIDTH}x{MIN_HEIGHT} and {MAX_WIDTH}x{MAX_HEIGHT} pixels."),
+            ));
+        }
+
+        let length = text.chars().count() as u32;
+        if self.width < render::min_width(length) {
+            return Err(ApiError::Any(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "{length} characters need a width of at least {} pixels.",
+                    render::min_width(length)
+                ),
+            ));
+        }
+
+        Ok(CaptchaStyle {
+            width: self.width,
+            height: self.height,
+            difficulty,
+            dark_mode,
+            foreground: self.foreground.map(HexColor::rgb),
+            background: self.background.map(HexColor::rgb),
+        })
+    }
+}
+
 #[derive(Serialize, ToSchema)]
 pub struct CaptchaResponse {
-    pub solution: String,
+    /// The id to verify the answer with
+    pub id: String,
+    /// The image of the captcha
     pub url: String,
+    /// The captcha read out loud as a WAV file, for users who can't see the image
+    pub audio_url: String,
+    /// How many seconds the captcha can be answered
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct VerifyCaptchaRequest {
+    pub id: String,
+    pub answer: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VerifyCaptchaResponse {
+    pub success: bool,
+    #[schema(inline)]
+    pub status: Verification,
+}
+
+fn validate(difficulty: u32, text: &str) -> Result<(), ApiError> {
+    if !(1..=10).contains(&difficulty) {
+        return Err(ApiError::Any(
+            StatusCode::BAD_REQUEST,
+            "The difficulty must be in between 1 and 10.".to_owned(),
+        ));
+    }
+
+    if !(1..=MAX_TEXT_LENGTH).contains(&text.chars().count()) {
+        return Err(ApiError::Any(
+            StatusCode::BAD_REQUEST,
+            format!("The captcha text must be between 1 and {MAX_TEXT_LENGTH} characters long."),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Draws `text` on a worker.
+async fn render(state: &AppState, text: String, style: CaptchaStyle) -> Result<RgbImage, ApiError> {
+    state
+        .workers
+        .run(move || render::render(&text, &style))
+        .await
+}
+
+async fn encode(
+    state: &AppState,
+    img: RgbImage,
+    output: ImageOutput,
+) -> Result<EncodedImage, ApiError> {
+    state
+        .workers
+        .run(move || output.encode(DynamicImage::ImageRgb8(img)))
+        .await?
 }
 
 #[utoipa::path(
     get,
     path = "/captcha", 
-    params(CaptchaQueryParams),
+    params(CaptchaQueryParams, CaptchaStyleQueryParams),
     responses(
-        (status = 200, content_type = "image/png", description = "The raw image")
+        (status = 200, body = inline(CaptchaResponse)),
+        (status = 503, description = "Too many captchas are waiting to be answered")
     )
 )]
 pub async fn generate_captcha_response(
+    State(state): State<AppState>,
     Query(captcha_params): Query<CaptchaQueryParams>,
+    Query(style_params): Query<CaptchaStyleQueryParams>,
 ) -> ApiResult<CaptchaResponse> {
-    if !(1..=10).contains(&captcha_params.difficulty) {
-        return Err(ApiError::Any(
+    if captcha_params.text.is_some() {
+        return Err(ApiError::AnyStatic(
             StatusCode::BAD_REQUEST,
-            "The difficulty must be in between 1 and 10.".to_owned(),
+            "The text of a challenge can't be chosen, use `/image/gen_captcha` to render a given text.",
         ));
     }
 
-    if !(1..=5).contains(&captcha_params.text.len()) {
-        return Err(ApiError::Any(
+    let (text, answer) = match captcha_params.mode.question() {
+        Some(question) => question,
+        None => {
+            if !(1..=MAX_TEXT_LENGTH).contains(&captcha_params.length) {
+                return Err(ApiError::Any(
+                    StatusCode::BAD_REQUEST,
+                    format!("`length` must be between 1 and {MAX_TEXT_LENGTH}."),
+                ));
+            }
+
+            let characters = captcha_params.charset.characters(
+                captcha_params.characters.as_deref(),
+                captcha_params.exclude_confusables,
+            )?;
+            let text = charset::random_text(&characters, captcha_params.length);
+            (text.clone(), text)
+        }
+    };
+    validate(captcha_params.difficulty, &text)?;
+    let style =
+        style_params.into_style(&text, captcha_params.difficulty, captcha_params.dark_mode)?;
+
+    let audio_noise = captcha_params
+        .audio_noise
+        .unwrap_or(captcha_params.difficulty as f32 / 20.0);
+    if !(0.0..=1.0).contains(&audio_noise) {
+        return Err(ApiError::AnyStatic(
             StatusCode::BAD_REQUEST,
-            "Captcha text length has to 5 or less.".to_owned(),
+            "`audioNoise` must be between 0 and 1.",
         ));
     }
 
+    let image = render(&state, text.clone(), style).await?;
+    let id = state.captchas.insert(Challenge {
+        text,
+        answer,
+        image: Arc::new(image),
+        case_sensitive: CONFIG.captcha_case_sensitive,
+        audio_noise,
+        audio_seed: rand::random(),
+    })?;
+
     Ok(Json(CaptchaResponse {
-        url: format!(
-            "https://api.mettwasser.xyz/image/gen_captcha?text={}&difficulty={}&darkMode={}",
-            &captcha_params.text, captcha_params.difficulty, captcha_params.dark_mode
-        ),
-        solution: captcha_params.text,
+        url: format!("https://api.mettwasser.xyz/image/captcha/{id}"),
+        audio_url: format!("https://api.mettwasser.xyz/utility/captcha/{id}/audio"),
+        id,
+        expires_in: state.captchas.ttl().as_secs(),
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/captcha/verify",
+    request_body = inline(VerifyCaptchaRequest),
+    responses(
+        (status = 200, body = inline(VerifyCaptchaResponse), description = "Whether the answer was right, every captcha can only be answered once")
+    )
+)]
+pub async fn verify_captcha(
+    State(state): State<AppState>,
+    Json(request): Json<VerifyCaptchaRequest>,
+) -> ApiResult<VerifyCaptchaResponse> {
+    let status = state.captchas.verify(&request.id, &request.answer);
+
+    Ok(Json(VerifyCaptchaResponse {
+        success: status == Verification::Success,
+        status,
     }))
 }
 
 #[utoipa::path(
     get,
-    path = "/gen_captcha",
-    params(GenCaptchaQueryParams),
+    path = "/captcha/{id}",
+    params(
+        ("id" = String, Path, description = "The id returned by `/utility/captcha`"),
+        OutputQueryParams
+    ),
     responses(
-        (status = 200, body = inline(CaptchaResponse))
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image"),
+        (status = 404, description = "The captcha doesn't exist or has expired")
     )
 )]
-pub async fn generate_captcha_image(
-    Query(captcha_params): Query<GenCaptchaQueryParams>,
-) -> Result<
-    (
-        AppendHeaders<[(header::HeaderName, &'static str); 1]>,
-        Vec<u8>,
+pub async fn captcha_challenge_image(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    output: ImageOutput,
+) -> Result<EncodedImage, ApiError> {
+    let challenge = state.captchas.get(&id).ok_or(ApiError::AnyStatic(
+        StatusCode::NOT_FOUND,
+        "The captcha doesn't exist or has expired.",
+    ))?;
+
+    encode(&state, challenge.image.as_ref().clone(), output).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/captcha/{id}/audio",
+    params(
+        ("id" = String, Path, description = "The id returned by `/utility/captcha`")
     ),
-    ApiError,
-> {
-    if !(1..=10).contains(&captcha_params.difficulty) {
-        return Err(ApiError::Any(
-            StatusCode::BAD_REQUEST,
-            "The difficulty must be in between 1 and 10.".to_owned(),
-        ));
-    }
+    responses(
+        (status = 200, content_type = "audio/wav", description = "The characters of the captcha read out one by one, upper case letters are announced if the answer is case-sensitive"),
+        (status = 404, description = "The captcha doesn't exist or has expired"),
+        (status = 422, description = "The captcha contains characters that can't be read out")
+    )
+)]
+pub async fn captcha_challenge_audio(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let challenge = state.captchas.get(&id).ok_or(ApiError::AnyStatic(
+        StatusCode::NOT_FOUND,
+        "The captcha doesn't exist or has expired.",
+    ))?;
 
-    if !(1..=5).contains(&captcha_params.text.len()) {
-        return Err(ApiError::Any(
-            StatusCode::BAD_REQUEST,
-            "Captcha text length has to 5 or less.".to_owned(),
-        ));
+    let unspeakable = || {
+        ApiError::AnyStatic(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Only letters, digits and arithmetic can be read out.",
+        )
+    };
+    // Rejected up front, so it doesn't wait for a worker.
+    if !speech::can_spell(&challenge.text) {
+        return Err(unspeakable());
     }
 
-    let captcha = tokio::task::spawn_blocking(move || {
-        CaptchaBuilder::new()
-            .compression(30)
-            .text(captcha_params.text)
-            .complexity(captcha_params.difficulty)
-            .dark_mode(captcha_params.dark_mode)
-            .width(160)
-            .height(40)
-            .build()
-    })
-    .await
-    .map_err(|_err| {
-        ApiError::Any(
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Something went wrong during captcha generation.".to_owned(),
-        )
-    })?;
+    let wav = state
+        .workers
+        .run(move || {
+            audio::render_wav(
+                &challenge.text,
+                challenge.case_sensitive,
+                challenge.audio_noise,
+                challenge.audio_seed,
+            )
+        })
+        .await?
+        .ok_or_else(unspeakable)?;
 
-    let mut buffer: Vec<u8> = Vec::new();
+    Ok(([(header::CONTENT_TYPE, "audio/wav")], wav))
+}
 
-    captcha
-        .image
-        .write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
-        .map_err(ApiError::from)?;
+#[utoipa::path(
+    get,
+    path = "/gen_captcha",
+    params(GenCaptchaQueryParams, CaptchaStyleQueryParams, OutputQueryParams),
+    responses(
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
+    )
+)]
+pub async fn generate_captcha_image(
+    State(state): State<AppState>,
+    Query(captcha_params): Query<GenCaptchaQueryParams>,
+    Query(style_params): Query<CaptchaStyleQueryParams>,
+    output: ImageOutput,
+) -> Result<EncodedImage, ApiError> {
+    validate(captcha_params.difficulty, &captcha_params.text)?;
+    let style = style_params.into_style(
+        &captcha_params.text,
+        captcha_params.difficulty,
+        captcha_params.dark_mode,
+    )?;
 
-    Ok((AppendHeaders([(header::CONTENT_TYPE, "image/png")]), buffer))
+    let img = render(&state, captcha_params.text, style).await?;
+    encode(&state, img, output).await
 }
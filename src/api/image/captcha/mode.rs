@@ -0,0 +1,86 @@
+use super::words::WORDS;
+use rand::{seq::SliceRandom, Rng};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptchaMode {
+    /// Random characters, see `length` and `charset`
+    #[default]
+    Text,
+    /// An addition, subtraction or multiplication like `7 + 4`, the answer is the result
+    Math,
+    /// A word from a built-in dictionary
+    Word,
+}
+
+impl CaptchaMode {
+    /// Comes up with the text that is shown and the expected answer, `None` for the `text` mode.
+    pub fn question(self) -> Option<(String, String)> {
+        let mut rng = rand::thread_rng();
+
+        match self {
+            Self::Text => None,
+            Self::Math => {
+                let (text, result) = match rng.gen_range(0..3) {
+                    0 => {
+                        let (a, b) = (rng.gen_range(1..=20), rng.gen_range(1..=20));
+                        (format!("{a} + {b}"), a + b)
+                    }
+                    1 => {
+                        let (a, b) = (rng.gen_range(2..=20), rng.gen_range(1..=20));
+                        let (a, b) = (a.max(b), a.min(b));
+                        (format!("{a} - {b}"), a - b)
+                    }
+                    _ => {
+                        let (a, b) = (rng.gen_range(2..=9), rng.gen_range(2..=9));
+                        (format!("{a} × {b}"), a * b)
+                    }
+                };
+
+                Some((text, result.to_string()))
+            }
+            Self::Word => {
+                let word = WORDS.choose(&mut rng).unwrap().to_string();
+                Some((word.clone(), word))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::render::min_width, *};
+
+    #[test]
+    fn answers_the_math_questions() {
+        for _ in 0..200 {
+            let (text, answer) = CaptchaMode::Math.question().unwrap();
+            let [a, operator, b] = text.split(' ').collect::<Vec<_>>()[..] else {
+                panic!("{text}");
+            };
+            let (a, b): (i32, i32) = (a.parse().unwrap(), b.parse().unwrap());
+            let result = match operator {
+                "+" => a + b,
+                "-" => a - b,
+                "×" => a * b,
+                _ => panic!("{text}"),
+            };
+
+            assert_eq!(answer, result.to_string(), "{text}");
+            assert!(result >= 0, "{text}");
+        }
+    }
+
+    #[test]
+    fn fits_every_question_in_the_default_size() {
+        let mut texts: Vec<String> = WORDS.iter().map(|word| word.to_string()).collect();
+        texts.extend((0..200).map(|_| CaptchaMode::Math.question().unwrap().0));
+
+        for text in texts {
+            assert!(min_width(text.chars().count() as u32) <= 160, "{text}");
+        }
+        assert_eq!(CaptchaMode::Text.question(), None);
+    }
+}
@@ -0,0 +1,208 @@
+//! Draws the captcha images with `captcha-rs`.
+//!
+//! `CaptchaBuilder` only knows its own colors and always draws the text at a fixed size, picked by the number of
+//! characters. The requested colors are applied afterwards, and [`min_width`] and [`MIN_HEIGHT`] keep the builder
+//! from panicking or squeezing the characters into each other.
+
+use captcha_rs::CaptchaBuilder;
+use image::{Rgb, RgbImage};
+
+/// The backgrounds `CaptchaBuilder` draws on.
+const LIGHT_BACKGROUND: [u8; 3] = [224, 238, 253];
+const DARK_BACKGROUND: [u8; 3] = [18, 18, 18];
+
+/// How far a pixel has to be from the background to be recolored to the full foreground, closer pixels are
+/// anti-aliased edges or noise and are blended.
+const INK_DISTANCE: f32 = 128.0;
+
+/// The shortest image the text fits in, `CaptchaBuilder` draws it 15 pixels above the middle.
+pub const MIN_HEIGHT: u32 = 40;
+
+/// The narrowest image that fits `length` characters, `CaptchaBuilder` leaves 5 pixels on both sides.
+pub fn min_width(length: u32) -> u32 {
+    // The widths of the average character at the font sizes `CaptchaBuilder` uses for 1-3, 4-5 and more characters.
+    let character_width = match length {
+        0..=3 => 30,
+        4..=5 => 24,
+        _ => 18,
+    };
+
+    10 + length * character_width
+}
+
+/// How a captcha image looks, everything but its text.
+#[derive(Debug, Clone)]
+pub struct CaptchaStyle {
+    pub width: u32,
+    pub height: u32,
+    pub difficulty: u32,
+    pub dark_mode: bool,
+    pub foreground: Option<[u8; 3]>,
+    pub background: Option<[u8; 3]>,
+}
+
+/// Draws `text` with interference lines, circles and, depending on the difficulty, noise.
+///
+/// The lines, circles and colors are random every time, so a stored challenge keeps the image it was drawn with.
+pub fn render(text: &str, style: &CaptchaStyle) -> RgbImage {
+    let mut img = CaptchaBuilder::new()
+        .text(text.to_owned())
+        .width(style.width)
+        .height(style.height)
+        .complexity(style.difficulty)
+        .dark_mode(style.dark_mode)
+        .build()
+        .image
+        .into_rgb8();
+
+    recolor(&mut img, style);
+    img
+}
+
+/// Replaces `CaptchaBuilder`'s background with `style.background` and everything drawn on it with `style.foreground`.
+fn recolor(img: &mut RgbImage, style: &CaptchaStyle) {
+    if style.foreground.is_none() && style.background.is_none() {
+        return;
+    }
+
+    let drawn_on = if style.dark_mode {
+        DARK_BACKGROUND
+    } else {
+        LIGHT_BACKGROUND
+    };
+    let background = style.background.unwrap_or(drawn_on);
+
+    for Rgb(pixel) in img.pixels_mut() {
+        let distance = pixel
+            .iter()
+            .zip(drawn_on)
+            .map(|(&channel, drawn_on)| (channel as f32 - drawn_on as f32).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        let ink = (distance / INK_DISTANCE).min(1.0);
+        let foreground = style.foreground.unwrap_or(*pixel);
+
+        for ((channel, background), foreground) in pixel.iter_mut().zip(background).zip(foreground)
+        {
+            *channel =
+                (background as f32 + (foreground as f32 - background as f32) * ink).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::CaptchaMode, *};
+
+    const STYLE: CaptchaStyle = CaptchaStyle {
+        width: 160,
+        height: 40,
+        difficulty: 1,
+        dark_mode: false,
+        foreground: Some([0, 0, 0]),
+        background: Some([255, 255, 255]),
+    };
+
+    /// How many solid 2x2 blocks of the foreground every character's column has. The strokes of a character are
+    /// solid, the lines and circles are a single pixel wide.
+    fn ink_per_character(img: &RgbImage, length: u32) -> Vec<usize> {
+        let ink = |x, y| img[(x, y)].0 == [0; 3];
+        let cell = (img.width() - 10) / length;
+
+        (0..length)
+            .map(|index| {
+                (5 + index * cell..5 + (index + 1) * cell)
+                    .flat_map(|x| (0..img.height() - 1).map(move |y| (x, y)))
+                    .filter(|&(x, y)| {
+                        ink(x, y) && ink(x + 1, y) && ink(x, y + 1) && ink(x + 1, y + 1)
+                    })
+                    .count()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draws_every_length_at_the_smallest_size() {
+        for length in 1..=16 {
+            let text = "WMbg8QA3ak4hRz2p".chars().take(length).collect::<String>();
+            let style = CaptchaStyle {
+                width: min_width(length as u32),
+                height: MIN_HEIGHT,
+                ..STYLE
+            };
+
+            for dark_mode in [false, true] {
+                let style = CaptchaStyle {
+                    dark_mode,
+                    ..style.clone()
+                };
+                let img = render(&text, &style);
+
+                assert_eq!(img.dimensions(), (style.width, style.height));
+                for (character, ink) in text.chars().zip(ink_per_character(&img, length as u32)) {
+                    // an empty column has up to about 25 from crossing lines and circles
+                    assert!(ink > 50, "{character} of {text}: {ink}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draws_the_questions_of_every_mode() {
+        for mode in [CaptchaMode::Math, CaptchaMode::Word] {
+            for _ in 0..20 {
+                let (text, _) = mode.question().unwrap();
+                let img = render(&text, &STYLE);
+                let length = text.chars().count() as u32;
+
+                // the operators and spaces are too small to tell apart from the lines
+                for (character, ink) in text.chars().zip(ink_per_character(&img, length)) {
+                    if character.is_ascii_alphanumeric() {
+                        assert!(ink > 50, "{character} of {text}: {ink}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draws_at_every_difficulty() {
+        for difficulty in 1..=10 {
+            let style = CaptchaStyle {
+                difficulty,
+                ..STYLE
+            };
+            assert_eq!(render("Ab3d", &style).dimensions(), (160, 40));
+        }
+
+        let style = CaptchaStyle {
+            difficulty: 10,
+            width: 1024,
+            height: 512,
+            ..STYLE
+        };
+        assert_eq!(render("Ab3d", &style).dimensions(), (1024, 512));
+    }
+
+    #[test]
+    fn uses_the_requested_colors() {
+        let background = |img: &RgbImage| img.pixels().filter(|pixel| pixel.0 == [255; 3]).count();
+        let img = render("Ab3d", &STYLE);
+
+        assert!(img
+            .pixels()
+            .all(|pixel| pixel.0[0] == pixel.0[1] && pixel.0[1] == pixel.0[2]));
+        assert!(img.pixels().any(|pixel| pixel.0 == [0; 3]));
+        assert!(background(&img) > 160 * 40 / 2);
+
+        // without a foreground, the characters keep their colors on the new background
+        let style = CaptchaStyle {
+            foreground: None,
+            dark_mode: true,
+            ..STYLE
+        };
+        let img = render("Ab3d", &style);
+        assert!(background(&img) > 160 * 40 / 2);
+        assert!(img.pixels().any(|pixel| pixel.0[0] != pixel.0[2]));
+    }
+}
@@ -0,0 +1,477 @@
+//! A small formant synthesizer that spells out letters and digits, so audio captchas don't need a text to speech service.
+//!
+//! The pronunciations were tuned by ear on a few rendered clips, they haven't been tested with other listeners or
+//! speech recognition. The tests only make sure every character gives an audible clip of a sensible length.
+
+use rand::Rng;
+use std::f32::consts::PI;
+
+pub const SAMPLE_RATE: u32 = 16_000;
+
+/// The bandwidths of the formants, the last two formants are fixed.
+const BANDWIDTHS: [f32; 5] = [60.0, 90.0, 150.0, 250.0, 200.0];
+const HIGH_FORMANTS: [f32; 2] = [3300.0, 3750.0];
+
+/// How fast the formants and amplitudes move towards the next sound.
+const FORMANT_SMOOTHING_MS: f32 = 12.0;
+const AMPLITUDE_SMOOTHING_MS: f32 = 4.0;
+
+/// Brings the frication noise to roughly the level of real fricatives, about 15 dB below the vowels.
+const FRICATION_GAIN: f32 = 3.5;
+
+/// A single sound, the synthesizer glides from one to the next.
+#[derive(Debug, Clone, Copy)]
+struct Phone {
+    formants: [f32; 3],
+    voicing: f32,
+    aspiration: f32,
+    frication: f32,
+    frication_freq: f32,
+    duration_ms: f32,
+}
+
+const fn voiced(formants: [f32; 3], voicing: f32, duration_ms: f32) -> Phone {
+    Phone {
+        formants,
+        voicing,
+        aspiration: 0.0,
+        frication: 0.0,
+        frication_freq: 0.0,
+        duration_ms,
+    }
+}
+
+const fn vowel(f1: f32, f2: f32, f3: f32, duration_ms: f32) -> Phone {
+    voiced([f1, f2, f3], 1.0, duration_ms)
+}
+
+const fn glide(f1: f32, f2: f32, f3: f32, duration_ms: f32) -> Phone {
+    voiced([f1, f2, f3], 0.7, duration_ms)
+}
+
+const fn nasal(f2: f32, f3: f32) -> Phone {
+    voiced([250.0, f2, f3], 0.3, 80.0)
+}
+
+const fn fricative(
+    locus: [f32; 3],
+    frication_freq: f32,
+    frication: f32,
+    voicing: f32,
+    duration_ms: f32,
+) -> Phone {
+    Phone {
+        formants: locus,
+        voicing,
+        aspiration: 0.0,
+        frication,
+        frication_freq,
+        duration_ms,
+    }
+}
+
+const fn closure(locus: [f32; 3], voicing: f32) -> Phone {
+    voiced(locus, voicing, 55.0)
+}
+
+const fn burst(locus: [f32; 3], frication_freq: f32) -> Phone {
+    fricative(locus, frication_freq, 0.5, 0.0, 12.0)
+}
+
+const fn aspiration(locus: [f32; 3]) -> Phone {
+    Phone {
+        formants: locus,
+        voicing: 0.0,
+        aspiration: 0.6,
+        frication: 0.0,
+        frication_freq: 0.0,
+        duration_ms: 45.0,
+    }
+}
+
+const LABIAL: [f32; 3] = [300.0, 800.0, 2200.0];
+const ALVEOLAR: [f32; 3] = [300.0, 1700.0, 2700.0];
+const VELAR: [f32; 3] = [300.0, 2000.0, 2300.0];
+const PALATAL: [f32; 3] = [300.0, 1900.0, 2600.0];
+const DENTAL: [f32; 3] = [300.0, 1400.0, 2700.0];
+
+const IY: &[Phone] = &[vowel(270.0, 2290.0, 3010.0, 170.0)];
+const IH: &[Phone] = &[vowel(390.0, 1990.0, 2550.0, 110.0)];
+const EH: &[Phone] = &[vowel(530.0, 1840.0, 2480.0, 130.0)];
+const AE: &[Phone] = &[vowel(660.0, 1720.0, 2410.0, 140.0)];
+const AA: &[Phone] = &[vowel(730.0, 1090.0, 2440.0, 160.0)];
+const AH: &[Phone] = &[vowel(640.0, 1190.0, 2390.0, 90.0)];
+const AO: &[Phone] = &[vowel(570.0, 840.0, 2410.0, 160.0)];
+const UW: &[Phone] = &[vowel(300.0, 870.0, 2240.0, 180.0)];
+const OW: &[Phone] = &[
+    vowel(550.0, 960.0, 2400.0, 120.0),
+    vowel(330.0, 800.0, 2300.0, 120.0),
+];
+const EY: &[Phone] = &[
+    vowel(480.0, 1900.0, 2500.0, 120.0),
+    vowel(300.0, 2200.0, 2900.0, 110.0),
+];
+const AY: &[Phone] = &[
+    vowel(730.0, 1150.0, 2440.0, 130.0),
+    vowel(330.0, 2100.0, 2800.0, 110.0),
+];
+
+const Y: &[Phone] = &[glide(260.0, 2070.0, 3020.0, 60.0)];
+const W: &[Phone] = &[glide(290.0, 610.0, 2150.0, 60.0)];
+const R: &[Phone] = &[glide(310.0, 1060.0, 1380.0, 90.0)];
+const L: &[Phone] = &[glide(330.0, 1050.0, 2880.0, 80.0)];
+const M: &[Phone] = &[nasal(1100.0, 2200.0)];
+const N: &[Phone] = &[nasal(1600.0, 2600.0)];
+
+const S: &[Phone] = &[fricative(ALVEOLAR, 5500.0, 0.5, 0.0, 130.0)];
+const Z: &[Phone] = &[fricative(ALVEOLAR, 5500.0, 0.35, 0.35, 120.0)];
+const F: &[Phone] = &[fricative(LABIAL, 6500.0, 0.2, 0.0, 120.0)];
+const V: &[Phone] = &[fricative(LABIAL, 6500.0, 0.15, 0.35, 100.0)];
+const TH: &[Phone] = &[fricative(DENTAL, 6000.0, 0.15, 0.0, 120.0)];
+
+const P: &[Phone] = &[
+    closure(LABIAL, 0.0),
+    burst(LABIAL, 1200.0),
+    aspiration(LABIAL),
+];
+const B: &[Phone] = &[closure(LABIAL, 0.15), burst(LABIAL, 1200.0)];
+const T: &[Phone] = &[
+    closure(ALVEOLAR, 0.0),
+    burst(ALVEOLAR, 4500.0),
+    aspiration(ALVEOLAR),
+];
+const D: &[Phone] = &[closure(ALVEOLAR, 0.15), burst(ALVEOLAR, 4500.0)];
+const K: &[Phone] = &[closure(VELAR, 0.0), burst(VELAR, 2200.0), aspiration(VELAR)];
+const CH: &[Phone] = &[
+    closure(PALATAL, 0.0),
+    burst(PALATAL, 2800.0),
+    fricative(PALATAL, 2800.0, 0.55, 0.0, 90.0),
+];
+const JH: &[Phone] = &[
+    closure(PALATAL, 0.15),
+    burst(PALATAL, 2800.0),
+    fricative(PALATAL, 2800.0, 0.35, 0.35, 80.0),
+];
+
+/// Said before upper case letters when the case matters.
+const CAPITAL: &[&[Phone]] = &[K, AE, P, IH, T, AH, L];
+
+/// How the name of a letter, digit or arithmetic operator is pronounced, `None` if it can't be spelled out.
+fn pronunciation(character: char) -> Option<&'static [&'static [Phone]]> {
+    Some(match character.to_ascii_lowercase() {
+        'a' => &[EY],
+        'b' => &[B, IY],
+        'c' => &[S, IY],
+        'd' => &[D, IY],
+        'e' => &[IY],
+        'f' => &[EH, F],
+        'g' => &[JH, IY],
+        'h' => &[EY, CH],
+        'i' => &[AY],
+        'j' => &[JH, EY],
+        'k' => &[K, EY],
+        'l' => &[EH, L],
+        'm' => &[EH, M],
+        'n' => &[EH, N],
+        'o' => &[OW],
+        'p' => &[P, IY],
+        'q' => &[K, Y, UW],
+        'r' => &[AA, R],
+        's' => &[EH, S],
+        't' => &[T, IY],
+        'u' => &[Y, UW],
+        'v' => &[V, IY],
+        'w' => &[D, AH, B, AH, L, Y, UW],
+        'x' => &[EH, K, S],
+        'y' => &[W, AY],
+        'z' => &[Z, IY],
+        '0' => &[Z, IH, R, OW],
+        '1' => &[W, AH, N],
+        '2' => &[T, UW],
+        '3' => &[TH, R, IY],
+        '4' => &[F, AO, R],
+        '5' => &[F, AY, V],
+        '6' => &[S, IH, K, S],
+        '7' => &[S, EH, V, AH, N],
+        '8' => &[EY, T],
+        '9' => &[N, AY, N],
+        '+' => &[P, L, AH, S],
+        '-' => &[M, AY, N, AH, S],
+        '×' => &[T, AY, M, Z],
+        _ => return None,
+    })
+}
+
+/// Whether every character of `text` but whitespace can be read out.
+pub fn can_spell(text: &str) -> bool {
+    text.chars()
+        .all(|character| character.is_whitespace() || pronunciation(character).is_some())
+}
+
+/// How a single character should be spoken.
+#[derive(Debug, Clone, Copy)]
+pub struct Voice {
+    /// Multiplies the pitch, `1.0` is a low voice
+    pub pitch: f32,
+    /// Multiplies the duration of every sound
+    pub tempo: f32,
+}
+
+/// Speaks the name of `character`, prefixed with "capital" if `announce_case` is set and it's upper case.
+///
+/// Returns `None` if [`can_spell`] rejects the character.
+pub fn speak(
+    character: char,
+    announce_case: bool,
+    voice: Voice,
+    rng: &mut impl Rng,
+) -> Option<Vec<f32>> {
+    let mut phones: Vec<Phone> = Vec::new();
+    if announce_case && character.is_ascii_uppercase() {
+        phones.extend(CAPITAL.iter().copied().flatten());
+        phones.push(closure(LABIAL, 0.0));
+    }
+    phones.extend(pronunciation(character)?.iter().copied().flatten());
+
+    Some(Synthesizer::new(phones[0]).render(&phones, voice, rng))
+}
+
+/// A two-pole resonator, as used by Klatt synthesizers.
+#[derive(Debug, Default, Clone, Copy)]
+struct Resonator {
+    y1: f32,
+    y2: f32,
+}
+
+impl Resonator {
+    /// Filters `x`, `peak_gain` normalizes the gain at the center frequency instead of at 0 Hz.
+    fn filter(&mut self, x: f32, freq: f32, bandwidth: f32, peak_gain: bool) -> f32 {
+        let r = (-PI * bandwidth / SAMPLE_RATE as f32).exp();
+        let theta = 2.0 * PI * freq / SAMPLE_RATE as f32;
+        let b = 2.0 * r * theta.cos();
+        let c = -r * r;
+        let a = if peak_gain {
+            (1.0 - r) * (1.0 - 2.0 * r * (2.0 * theta).cos() + r * r).sqrt()
+        } else {
+            1.0 - b - c
+        };
+
+        let y = a * x + b * self.y1 + c * self.y2;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+struct Synthesizer {
+    current: Phone,
+    cascade: [Resonator; 5],
+    frication: Resonator,
+    phase: f32,
+    last_pulse: f32,
+}
+
+impl Synthesizer {
+    fn new(first: Phone) -> Self {
+        Self {
+            current: Phone {
+                voicing: 0.0,
+                aspiration: 0.0,
+                frication: 0.0,
+                ..first
+            },
+            cascade: Default::default(),
+            frication: Resonator::default(),
+            phase: 0.0,
+            last_pulse: 0.0,
+        }
+    }
+
+    fn render(mut self, phones: &[Phone], voice: Voice, rng: &mut impl Rng) -> Vec<f32> {
+        let per_ms = SAMPLE_RATE as f32 / 1000.0;
+        let formant_step = 1.0 - (-1.0 / (FORMANT_SMOOTHING_MS * per_ms)).exp();
+        let amplitude_step = 1.0 - (-1.0 / (AMPLITUDE_SMOOTHING_MS * per_ms)).exp();
+
+        // Every target is followed by a short fade out, so the last sound doesn't get cut off.
+        let silence = Phone {
+            voicing: 0.0,
+            aspiration: 0.0,
+            frication: 0.0,
+            duration_ms: 30.0,
+            ..phones[phones.len() - 1]
+        };
+        let targets: Vec<(Phone, usize)> = phones
+            .iter()
+            .chain([&silence])
+            .map(|phone| (*phone, (phone.duration_ms * voice.tempo * per_ms) as usize))
+            .collect();
+        let total: usize = targets.iter().map(|(_, samples)| samples).sum();
+
+        let mut samples = Vec::with_capacity(total);
+        for (target, length) in targets {
+            for _ in 0..length {
+                let current = &mut self.current;
+                for (formant, goal) in current.formants.iter_mut().zip(target.formants) {
+                    *formant += (goal - *formant) * formant_step;
+                }
+                current.voicing += (target.voicing - current.voicing) * amplitude_step;
+                current.aspiration += (target.aspiration - current.aspiration) * amplitude_step;
+                current.frication += (target.frication - current.frication) * amplitude_step;
+                if target.frication_freq > 0.0 {
+                    current.frication_freq = target.frication_freq;
+                }
+
+                // The pitch falls over the whole utterance, like a statement.
+                let progress = samples.len() as f32 / total as f32;
+                let f0 = voice.pitch * (130.0 - 35.0 * progress);
+
+                samples.push(self.sample(f0, rng));
+            }
+        }
+
+        samples
+    }
+
+    fn sample(&mut self, f0: f32, rng: &mut impl Rng) -> f32 {
+        // A Rosenberg glottal pulse, differentiated for the radiation at the lips.
+        self.phase = (self.phase + f0 / SAMPLE_RATE as f32).fract();
+        let pulse = match self.phase {
+            phase if phase < 0.4 => 0.5 * (1.0 - (PI * phase / 0.4).cos()),
+            phase if phase < 0.56 => (PI * (phase - 0.4) / 0.32).cos(),
+            _ => 0.0,
+        };
+        let glottal = (pulse - self.last_pulse) * 40.0;
+        self.last_pulse = pulse;
+
+        let noise = rng.gen_range(-1.0f32..1.0);
+        let current = self.current;
+
+        let mut voiced =
+            current.voicing * glottal + (current.aspiration + 0.02 * current.voicing) * noise;
+        let formants = current.formants.iter().chain(&HIGH_FORMANTS);
+        for ((resonator, &freq), bandwidth) in self.cascade.iter_mut().zip(formants).zip(BANDWIDTHS)
+        {
+            voiced = resonator.filter(voiced, freq, bandwidth, false);
+        }
+
+        let fricated = if current.frication > 0.0 {
+            let freq = current.frication_freq.min(SAMPLE_RATE as f32 * 0.45);
+            self.frication.filter(noise, freq, freq * 0.35, true)
+                * current.frication
+                * FRICATION_GAIN
+        } else {
+            0.0
+        };
+
+        voiced + fricated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{words::WORDS, CaptchaCharset, CaptchaMode},
+        *,
+    };
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn spells_every_charset() {
+        for charset in [
+            CaptchaCharset::Alphanumeric,
+            CaptchaCharset::Letters,
+            CaptchaCharset::Digits,
+        ] {
+            let characters: String = charset
+                .characters(None, false)
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert!(can_spell(&characters), "{charset:?}: {characters}");
+        }
+    }
+
+    #[test]
+    fn spells_every_question() {
+        assert!(WORDS.iter().all(|word| can_spell(word)));
+        for _ in 0..100 {
+            let (text, _) = CaptchaMode::Math.question().unwrap();
+            assert!(can_spell(&text), "{text}");
+        }
+    }
+
+    #[test]
+    fn speaks_every_character_audibly() {
+        let mut characters: Vec<char> = CaptchaCharset::Alphanumeric
+            .characters(None, false)
+            .unwrap();
+        characters.extend(WORDS.iter().flat_map(|word| word.chars()));
+        characters.extend(['+', '-', '×']);
+        characters.sort_unstable();
+        characters.dedup();
+
+        // the extremes of the voices in the audio captchas
+        let voices = [(0.8, 0.8), (0.8, 1.2), (1.4, 0.8), (1.4, 1.2)]
+            .map(|(pitch, tempo)| Voice { pitch, tempo });
+        let ms = |samples: &[f32]| samples.len() as u32 * 1000 / SAMPLE_RATE;
+
+        let mut clips = Vec::new();
+        for &character in &characters {
+            for voice in voices {
+                for announce_case in [false, true] {
+                    let mut rng = StdRng::seed_from_u64(0);
+                    let samples = speak(character, announce_case, voice, &mut rng).unwrap();
+                    let rms = (samples.iter().map(|sample| sample * sample).sum::<f32>()
+                        / samples.len() as f32)
+                        .sqrt();
+                    let max_ms = if announce_case && character.is_ascii_uppercase() {
+                        2000
+                    } else {
+                        1000
+                    };
+
+                    assert!(
+                        samples.iter().all(|sample| sample.is_finite()),
+                        "{character}"
+                    );
+                    assert!(
+                        (100..=max_ms).contains(&ms(&samples)),
+                        "{character}: {} ms",
+                        ms(&samples)
+                    );
+                    assert!(rms > 0.1, "{character}: {rms}");
+                }
+            }
+
+            // upper case letters are pronounced like lower case ones
+            if !character.is_ascii_uppercase() {
+                let mut rng = StdRng::seed_from_u64(0);
+                clips.push((
+                    character,
+                    speak(character, false, voices[0], &mut rng).unwrap(),
+                ));
+            }
+        }
+
+        // every character sounds different, even with the same voice and noise
+        for (i, (character, clip)) in clips.iter().enumerate() {
+            assert!(
+                clips[..i].iter().all(|(_, other)| other != clip),
+                "{character}"
+            );
+        }
+    }
+
+    #[test]
+    fn refuses_unknown_characters() {
+        let voice = Voice {
+            pitch: 1.0,
+            tempo: 1.0,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert!(!can_spell("ab€"));
+        assert!(speak('€', false, voice, &mut rng).is_none());
+        assert!(speak('a', false, voice, &mut rng).is_some_and(|samples| !samples.is_empty()));
+    }
+}
@@ -0,0 +1,18 @@
+/// Short, common English words for the `word` captcha mode.
+pub const WORDS: &[&str] = &[
+    "apple", "arrow", "badge", "baker", "beach", "berry", "black", "blank", "bread", "brick",
+    "bridge", "brush", "cabin", "camel", "candy", "chair", "chalk", "cheese", "cherry", "chess",
+    "cloud", "clock", "coast", "cocoa", "coral", "crane", "cream", "crown", "dance", "desert",
+    "dream", "drum", "eagle", "earth", "fable", "feather", "fence", "field", "flame", "flute",
+    "forest", "frame", "frost", "fruit", "garden", "ghost", "giant", "glass", "globe", "grape",
+    "grass", "green", "guitar", "harbor", "heart", "honey", "horse", "house", "island", "jacket",
+    "jelly", "jungle", "kettle", "kite", "knife", "ladder", "lemon", "light", "lion", "lizard",
+    "magic", "maple", "market", "meadow", "melon", "mirror", "money", "monkey", "moon", "mountain",
+    "music", "needle", "night", "ocean", "orange", "otter", "paint", "panda", "paper", "party",
+    "peach", "pearl", "pencil", "pepper", "piano", "pilot", "pizza", "planet", "plant", "pocket",
+    "purple", "queen", "quiet", "rabbit", "radio", "river", "robot", "rocket", "sand", "scarf",
+    "seven", "shadow", "sheep", "shell", "silver", "smile", "snake", "spider", "spoon", "stone",
+    "storm", "sugar", "summer", "table", "tiger", "toast", "tower", "train", "travel", "tulip",
+    "turtle", "uncle", "valley", "violin", "wagon", "water", "whale", "wheel", "window", "winter",
+    "wizard", "yellow", "zebra",
+];
@@ -0,0 +1,275 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A color in the CIE L*a*b* color space (D65 white point).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+pub fn srgb_to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(channel: f32) -> u8 {
+    let c = if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1.0 / 2.4) - 0.055
+    };
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+impl Lab {
+    pub fn from_rgb(rgb: [u8; 3]) -> Self {
+        let [r, g, b] = rgb.map(srgb_to_linear);
+
+        let x = 0.4124564 * r + 0.3575761 * g + 0.1804375 * b;
+        let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+        let z = 0.0193339 * r + 0.119192 * g + 0.9503041 * b;
+
+        let f = |t: f32| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(x / WHITE[0]), f(y / WHITE[1]), f(z / WHITE[2]));
+
+        Self {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    pub fn to_rgb(self) -> [u8; 3] {
+        let fy = (self.l + 16.0) / 116.0;
+        let fx = fy + self.a / 500.0;
+        let fz = fy - self.b / 200.0;
+
+        let f_inv = |t: f32| {
+            if t.powi(3) > 216.0 / 24389.0 {
+                t.powi(3)
+            } else {
+                (116.0 * t - 16.0) / (24389.0 / 27.0)
+            }
+        };
+        let (x, y, z) = (
+            f_inv(fx) * WHITE[0],
+            f_inv(fy) * WHITE[1],
+            f_inv(fz) * WHITE[2],
+        );
+
+        let r = 3.2404542 * x - 1.5371385 * y - 0.4985314 * z;
+        let g = -0.969266 * x + 1.8760108 * y + 0.041556 * z;
+        let b = 0.0556434 * x - 0.2040259 * y + 1.0572252 * z;
+
+        [r, g, b].map(linear_to_srgb)
+    }
+
+    /// The squared CIE76 distance, which is enough for comparing distances.
+    pub fn distance_squared(self, other: Lab) -> f32 {
+        (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl From<[u8; 3]> for Rgb {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Self { r, g, b }
+    }
+}
+
+/// The hue (0-360), minimum/maximum channel and chroma of a color, shared by HSL and HSV.
+fn hue_chroma(rgb: [u8; 3]) -> (f32, f32, f32, f32) {
+    let [r, g, b] = rgb.map(|channel| channel as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+
+    let hue = if chroma == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / chroma).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / chroma + 2.0)
+    } else {
+        60.0 * ((r - g) / chroma + 4.0)
+    };
+
+    (hue, min, max, chroma)
+}
+
+/// A color as hue (0-360), saturation (0-100) and lightness (0-100).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Hsl {
+    pub h: f32,
+    pub s: f32,
+    pub l: f32,
+}
+
+impl Hsl {
+    pub fn from_rgb(rgb: [u8; 3]) -> Self {
+        let (h, min, max, chroma) = hue_chroma(rgb);
+        let l = (max + min) / 2.0;
+        let s = if chroma == 0.0 {
+            0.0
+        } else {
+            chroma / (1.0 - (2.0 * l - 1.0).abs())
+        };
+
+        Self {
+            h,
+            s: s * 100.0,
+            l: l * 100.0,
+        }
+    }
+}
+
+/// Converts a hue in degrees and saturation/lightness from 0 to 1 to RGB channels from 0 to 1.
+pub fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let hue = hue.rem_euclid(360.0);
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+
+    let channel = |offset: f32| {
+        let k = (offset + hue / 30.0) % 12.0;
+        lightness - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+
+    [channel(0.0), channel(8.0), channel(4.0)]
+}
+
+/// A color as hue (0-360), saturation (0-100) and value (0-100).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Hsv {
+    pub h: f32,
+    pub s: f32,
+    pub v: f32,
+}
+
+impl Hsv {
+    pub fn from_rgb(rgb: [u8; 3]) -> Self {
+        let (h, _, max, chroma) = hue_chroma(rgb);
+        let s = if max == 0.0 { 0.0 } else { chroma / max };
+
+        Self {
+            h,
+            s: s * 100.0,
+            v: max * 100.0,
+        }
+    }
+}
+
+/// A color as cyan, magenta, yellow and key (black), each from 0 to 100.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+pub struct Cmyk {
+    pub c: f32,
+    pub m: f32,
+    pub y: f32,
+    pub k: f32,
+}
+
+impl Cmyk {
+    pub fn from_rgb(rgb: [u8; 3]) -> Self {
+        let [r, g, b] = rgb.map(|channel| channel as f32 / 255.0);
+        let max = r.max(g).max(b);
+
+        if max == 0.0 {
+            return Self {
+                c: 0.0,
+                m: 0.0,
+                y: 0.0,
+                k: 100.0,
+            };
+        }
+
+        let ink = |channel: f32| (max - channel) / max * 100.0;
+        Self {
+            c: ink(r),
+            m: ink(g),
+            y: ink(b),
+            k: (1.0 - max) * 100.0,
+        }
+    }
+}
+
+/// The relative luminance as defined by WCAG, from 0 (black) to 1 (white).
+pub fn relative_luminance(rgb: [u8; 3]) -> f32 {
+    let [r, g, b] = rgb.map(srgb_to_linear);
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Whether black or white text is easier to read on `rgb`, whichever has the higher WCAG contrast ratio.
+pub fn readable_text_color(rgb: [u8; 3]) -> [u8; 3] {
+    let luminance = relative_luminance(rgb);
+    let on_black = (luminance + 0.05) / 0.05;
+    let on_white = 1.05 / (luminance + 0.05);
+
+    if on_black >= on_white {
+        [0, 0, 0]
+    } else {
+        [255, 255, 255]
+    }
+}
+
+lazy_static! {
+    static ref NAMED_COLORS: Vec<(Lab, [u8; 3], &'static str)> = color_names::COLOR_MAP
+        .entries()
+        .map(|(rgb, name)| (Lab::from_rgb(*rgb), *rgb, *name))
+        .collect();
+}
+
+/// The named color closest to `rgb` with its value and CIE76 ΔE distance, a ΔE of about 2.3 is just noticeable.
+pub fn nearest_named_color(rgb: [u8; 3]) -> (&'static str, [u8; 3], f32) {
+    let lab = Lab::from_rgb(rgb);
+
+    NAMED_COLORS
+        .iter()
+        .map(|(named, named_rgb, name)| (*name, *named_rgb, lab.distance_squared(*named)))
+        .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))
+        .map(|(name, named_rgb, distance)| (name, named_rgb, distance.sqrt()))
+        .expect("the color map isn't empty")
+}
+
+/// Converts linear RGB to Oklab, a perceptual color space that interpolates without muddy midpoints.
+pub fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222147 * r + 0.53633254 * g + 0.05144599 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+
+    [
+        0.21045426 * l + 0.7936178 * m - 0.00407205 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.02590404 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+pub fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = (l + 0.39633778 * a + 0.21580376 * b).powi(3);
+    let m_ = (l - 0.10556135 * a - 0.06385417 * b).powi(3);
+    let s_ = (l - 0.08948418 * a - 1.2914855 * b).powi(3);
+
+    [
+        4.0767417 * l_ - 3.3077116 * m_ + 0.23096993 * s_,
+        -1.268438 * l_ + 2.6097574 * m_ - 0.3413194 * s_,
+        -0.00419609 * l_ - 0.7034186 * m_ + 1.7076147 * s_,
+    ]
+}
@@ -1,70 +1,326 @@
+use super::{
+    colorspace::{nearest_named_color, readable_text_color, Cmyk, Hsl, Hsv, Lab, Rgb},
+    source::{ImageUploadForm, ImageUrlQueryParams},
+};
 use crate::{
     error::ApiError,
-    extract::{Json, Query},
-    utils::{fetch_raw_image, image_from_bytes, rgb_to_hex},
+    extract::{ImageUpload, Json, Query},
+    fetch::fetch_raw_image,
+    state::AppState,
+    utils::{image_from_bytes, rgb_to_hex},
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use image::Pixel;
+use image::{imageops, Pixel, RgbaImage};
+use palette::PaletteAlgorithm;
+pub use palette::Swatch;
 use serde::{Deserialize, Serialize};
 use serde_default_utils::default_usize;
-use std::collections::HashMap;
 use utoipa::{IntoParams, ToSchema};
 
+mod palette;
+
+/// The most colors a clustering algorithm may be asked for.
+const MAX_CLUSTERS: usize = 256;
+
 #[derive(Debug, Deserialize, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct DominantColorQueryParams {
-    url: String,
-
     #[serde(default = "default_usize::<10>")]
     #[param(default = 10)]
     limit: usize,
+    /// How the colors are grouped, anything but `exact` returns at most 256 colors
+    #[serde(default)]
+    #[param(inline)]
+    algorithm: PaletteAlgorithm,
+    /// The seed for `kmeans`, the same seed always gives the same palette
+    #[serde(default)]
+    #[param(default = 0)]
+    seed: u64,
+    /// Pixels that are more transparent than this are ignored, `0` counts every pixel and `1` skips fully
+    /// transparent ones
+    #[serde(default)]
+    #[param(default = 0)]
+    alpha_threshold: u8,
+    /// Downscales the analyzed region so its longest side is at most this many pixels
+    #[param(minimum = 1)]
+    max_side: Option<u32>,
+    /// The left edge of the analyzed region
+    #[serde(default)]
+    crop_x: u32,
+    /// The top edge of the analyzed region
+    #[serde(default)]
+    crop_y: u32,
+    /// The width of the analyzed region, defaults to the rest of the image
+    crop_width: Option<u32>,
+    /// The height of the analyzed region, defaults to the rest of the image
+    crop_height: Option<u32>,
+    /// Only analyzes the center of the image, as a fraction of its width and height (can't be combined with a crop)
+    #[param(minimum = 0.0, maximum = 1.0)]
+    focus: Option<f32>,
+}
+
+/// The header that reports how many pixels were analyzed, the body stays the plain list of colors.
+const PIXELS_CONSIDERED: &str = "x-pixels-considered";
+
+/// The colors, ordered by most-dominant, and how many pixels they were taken from.
+pub struct DominantColors {
+    /// How many pixels were analyzed, after cropping, downscaling and skipping transparent pixels
+    pixels_considered: u32,
+    colors: Vec<DominantColorEntry>,
+}
+
+impl IntoResponse for DominantColors {
+    fn into_response(self) -> Response {
+        (
+            [(PIXELS_CONSIDERED, self.pixels_considered.to_string())],
+            Json(self.colors),
+        )
+            .into_response()
+    }
 }
 
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct DominantColorEntry {
     color: String,
+    /// The name of the color, if it has an exact match
     color_name: Option<&'static str>,
     pixels_counted: u32,
+    /// The share of the considered pixels this color stands for
+    percentage: f32,
+    rgb: Rgb,
+    hsl: Hsl,
+    hsv: Hsv,
+    cmyk: Cmyk,
+    lab: Lab,
+    nearest_color: NearestColor,
+    /// Black or white, whichever is easier to read on top of this color
+    text_color: String,
+}
+
+impl DominantColorEntry {
+    fn new(rgb: [u8; 3], pixels_counted: u32, percentage: f32) -> Self {
+        let (name, nearest_rgb, delta_e) = nearest_named_color(rgb);
+
+        Self {
+            color: rgb_to_hex(&rgb),
+            color_name: color_names::rgb_to_color_name(&rgb),
+            pixels_counted,
+            percentage,
+            rgb: rgb.into(),
+            hsl: Hsl::from_rgb(rgb),
+            hsv: Hsv::from_rgb(rgb),
+            cmyk: Cmyk::from_rgb(rgb),
+            lab: Lab::from_rgb(rgb),
+            nearest_color: NearestColor {
+                name,
+                color: rgb_to_hex(&nearest_rgb),
+                delta_e,
+            },
+            text_color: rgb_to_hex(&readable_text_color(rgb)),
+        }
+    }
+}
+
+/// The closest color that has a name.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NearestColor {
+    name: &'static str,
+    color: String,
+    /// The CIE76 color difference to the analyzed color, `0` is an exact match
+    delta_e: f32,
 }
 
 #[utoipa::path(
     get,
-    path = "/dominant_colors", 
-    params(DominantColorQueryParams),
+    path = "/dominant_colors",
+    params(ImageUrlQueryParams, DominantColorQueryParams),
     responses(
-        (status = 200, description = "The pixels of the image (ordered by most-dominant)", body = inline(DominantColorEntry))
+        (status = 200, description = "The dominant colors of the image, ordered by most-dominant", body = inline(Vec<DominantColorEntry>),
+            headers(("x-pixels-considered" = u32, description = "How many pixels were analyzed, after cropping, downscaling and skipping transparent pixels")))
     )
 )]
 pub async fn dominant_colors(
+    State(state): State<AppState>,
+    Query(source): Query<ImageUrlQueryParams>,
     Query(query_params): Query<DominantColorQueryParams>,
-) -> Result<Json<Vec<DominantColorEntry>>, ApiError> {
-    let raw_img = fetch_raw_image(&query_params.url).await?;
-    let img = image_from_bytes(raw_img)?;
+) -> Result<DominantColors, ApiError> {
+    let raw_img = fetch_raw_image(&state, &source.url).await?;
+
+    state
+        .workers
+        .run(move || dominant_colors_of_bytes(raw_img, query_params))
+        .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/dominant_colors",
+    params(DominantColorQueryParams),
+    request_body(
+        content = inline(ImageUploadForm),
+        content_type = ["multipart/form-data", "image/*"],
+        description = "The image that should be analyzed (max. 3mb by default)"
+    ),
+    responses(
+        (status = 200, description = "The dominant colors of the image, ordered by most-dominant", body = inline(Vec<DominantColorEntry>),
+            headers(("x-pixels-considered" = u32, description = "How many pixels were analyzed, after cropping, downscaling and skipping transparent pixels")))
+    )
+)]
+pub async fn dominant_colors_upload(
+    State(state): State<AppState>,
+    Query(query_params): Query<DominantColorQueryParams>,
+    ImageUpload(raw_img): ImageUpload,
+) -> Result<DominantColors, ApiError> {
+    state
+        .workers
+        .run(move || dominant_colors_of_bytes(raw_img, query_params))
+        .await?
+}
+
+fn dominant_colors_of_bytes(
+    raw_img: Bytes,
+    query_params: DominantColorQueryParams,
+) -> Result<DominantColors, ApiError> {
+    let (pixels_considered, swatches) = analyze(raw_img, query_params)?;
+    let total = pixels_considered.max(1) as f32;
+
+    let colors = swatches
+        .into_iter()
+        .map(|swatch| {
+            DominantColorEntry::new(
+                swatch.rgb,
+                swatch.count,
+                swatch.count as f32 / total * 100.0,
+            )
+        })
+        .collect();
+
+    Ok(DominantColors {
+        pixels_considered,
+        colors,
+    })
+}
 
-    let mut color_count: HashMap<[u8; 3], u32> = HashMap::new();
+/// Extracts the dominant colors of an image, returning how many pixels were considered and the colors.
+pub(super) fn analyze(
+    raw_img: Bytes,
+    query_params: DominantColorQueryParams,
+) -> Result<(u32, Vec<Swatch>), ApiError> {
+    let DominantColorQueryParams {
+        limit,
+        algorithm,
+        seed,
+        alpha_threshold,
+        max_side,
+        ..
+    } = query_params;
 
-    for pixel in img.pixels() {
-        let rgb = pixel.to_rgb();
-        *color_count.entry(rgb.0).or_insert(0) += 1;
+    if !matches!(algorithm, PaletteAlgorithm::Exact) && !(1..=MAX_CLUSTERS).contains(&limit) {
+        return Err(ApiError::Any(
+            StatusCode::BAD_REQUEST,
+            format!("`limit` must be between 1 and {MAX_CLUSTERS} for this algorithm."),
+        ));
     }
 
-    let mut dominant_colors: Vec<DominantColorEntry> = Vec::with_capacity(color_count.len());
+    if max_side == Some(0) {
+        return Err(ApiError::AnyStatic(
+            StatusCode::BAD_REQUEST,
+            "`max_side` must be at least 1.",
+        ));
+    }
 
-    for (rgb, count) in color_count {
-        let dominant_color = DominantColorEntry {
-            color: rgb_to_hex(&rgb),
-            color_name: color_names::rgb_to_color_name(&rgb),
-            pixels_counted: count,
-        };
-        dominant_colors.push(dominant_color);
+    let img = image_from_bytes(raw_img)?;
+    let (x, y, width, height) = analysis_region(&img, &query_params)?;
+    let mut region = imageops::crop_imm(&img, x, y, width, height).to_image();
+
+    if let Some(max_side) = max_side {
+        if width.max(height) > max_side {
+            let scale = max_side as f64 / width.max(height) as f64;
+            let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+            region = imageops::thumbnail(&region, scaled(width), scaled(height));
+        }
     }
 
-    dominant_colors.sort_by(|a, b| b.pixels_counted.cmp(&a.pixels_counted));
+    let pixels: Vec<[u8; 3]> = region
+        .pixels()
+        .filter(|pixel| pixel.0[3] >= alpha_threshold)
+        .map(|pixel| pixel.to_rgb().0)
+        .collect();
+
+    Ok((pixels.len() as u32, algorithm.palette(&pixels, limit, seed)))
+}
+
+/// The `(x, y, width, height)` of the part of the image that should be analyzed.
+fn analysis_region(
+    img: &RgbaImage,
+    query_params: &DominantColorQueryParams,
+) -> Result<(u32, u32, u32, u32), ApiError> {
+    let (img_width, img_height) = img.dimensions();
+    let cropped = query_params.crop_x != 0
+        || query_params.crop_y != 0
+        || query_params.crop_width.is_some()
+        || query_params.crop_height.is_some();
+
+    let region = match query_params.focus {
+        Some(_) if cropped => {
+            return Err(ApiError::AnyStatic(
+                StatusCode::BAD_REQUEST,
+                "`focus` can't be combined with a crop.",
+            ))
+        }
+        Some(focus) if !(focus > 0.0 && focus <= 1.0) => {
+            return Err(ApiError::AnyStatic(
+                StatusCode::BAD_REQUEST,
+                "`focus` must be greater than 0 and at most 1.",
+            ))
+        }
+        Some(focus) => {
+            let width = ((img_width as f32 * focus).round() as u32).clamp(1, img_width);
+            let height = ((img_height as f32 * focus).round() as u32).clamp(1, img_height);
+            (
+                (img_width - width) / 2,
+                (img_height - height) / 2,
+                width,
+                height,
+            )
+        }
+        None => {
+            let (x, y) = (query_params.crop_x, query_params.crop_y);
+            if x >= img_width || y >= img_height {
+                return Err(ApiError::Any(
+                    StatusCode::BAD_REQUEST,
+                    format!("The crop starts outside of the {img_width}x{img_height} image."),
+                ));
+            }
+
+            (
+                x,
+                y,
+                query_params
+                    .crop_width
+                    .unwrap_or(img_width - x)
+                    .min(img_width - x),
+                query_params
+                    .crop_height
+                    .unwrap_or(img_height - y)
+                    .min(img_height - y),
+            )
+        }
+    };
+
+    if region.2 == 0 || region.3 == 0 {
+        return Err(ApiError::AnyStatic(
+            StatusCode::BAD_REQUEST,
+            "The crop must be at least 1x1 pixels.",
+        ));
+    }
 
-    Ok(Json(
-        dominant_colors
-            .into_iter()
-            .take(query_params.limit)
-            .collect(),
-    ))
+    Ok(region)
 }
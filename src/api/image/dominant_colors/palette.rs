@@ -0,0 +1,526 @@
+use crate::api::image::colorspace::Lab;
+use rand::{distributions::WeightedIndex, prelude::Distribution, rngs::StdRng, SeedableRng};
+use serde::Deserialize;
+use std::{cmp::Reverse, collections::HashMap};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteAlgorithm {
+    /// Counts every exact color
+    #[default]
+    Exact,
+    /// Repeatedly splits the color space at the median of its widest channel
+    MedianCut,
+    /// Clusters the colors in the Lab color space
+    #[serde(alias = "k_means")]
+    Kmeans,
+    /// Merges the least common colors of an octree until enough are left
+    Octree,
+}
+
+/// A representative color and the number of pixels it stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Swatch {
+    pub rgb: [u8; 3],
+    pub count: u32,
+}
+
+impl PaletteAlgorithm {
+    /// Builds a palette of at most `limit` colors, ordered by how many pixels they stand for.
+    pub fn palette(self, pixels: &[[u8; 3]], limit: usize, seed: u64) -> Vec<Swatch> {
+        use PaletteAlgorithm::*;
+
+        let mut swatches = match self {
+            Exact => exact(pixels),
+            MedianCut => median_cut(histogram(pixels), limit),
+            Kmeans => kmeans(histogram(pixels), limit, seed),
+            Octree => octree(histogram(pixels), limit),
+        };
+
+        // ties are broken by color, so the order doesn't depend on hashing
+        swatches.sort_by_key(|swatch| (Reverse(swatch.count), swatch.rgb));
+        swatches.truncate(limit);
+        swatches
+    }
+}
+
+fn exact(pixels: &[[u8; 3]]) -> Vec<Swatch> {
+    let mut color_count: HashMap<[u8; 3], u32> = HashMap::new();
+
+    for rgb in pixels {
+        *color_count.entry(*rgb).or_insert(0) += 1;
+    }
+
+    color_count
+        .into_iter()
+        .map(|(rgb, count)| Swatch { rgb, count })
+        .collect()
+}
+
+/// Buckets the pixels by their 5 most significant bits per channel, keeping the average color of every bucket.
+///
+/// This keeps the input of the clustering algorithms small without losing much precision.
+fn histogram(pixels: &[[u8; 3]]) -> Vec<Swatch> {
+    let mut bins = vec![([0u64; 3], 0u32); 1 << 15];
+
+    for rgb in pixels {
+        let index =
+            (rgb[0] as usize >> 3) << 10 | (rgb[1] as usize >> 3) << 5 | rgb[2] as usize >> 3;
+        let (sum, count) = &mut bins[index];
+        for (sum, channel) in sum.iter_mut().zip(rgb) {
+            *sum += *channel as u64;
+        }
+        *count += 1;
+    }
+
+    bins.into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(sum, count)| Swatch {
+            rgb: sum.map(|sum| (sum / count as u64) as u8),
+            count,
+        })
+        .collect()
+}
+
+fn weighted_average(colors: &[Swatch]) -> Swatch {
+    let count: u32 = colors.iter().map(|color| color.count).sum();
+    let mut sum = [0u64; 3];
+
+    for color in colors {
+        for (sum, channel) in sum.iter_mut().zip(color.rgb) {
+            *sum += channel as u64 * color.count as u64;
+        }
+    }
+
+    Swatch {
+        rgb: sum.map(|sum| (sum / count.max(1) as u64) as u8),
+        count,
+    }
+}
+
+fn median_cut(colors: Vec<Swatch>, limit: usize) -> Vec<Swatch> {
+    if colors.is_empty() || limit == 0 {
+        return Vec::new();
+    }
+
+    let mut boxes = vec![colors];
+
+    while boxes.len() < limit {
+        // split the box with the widest channel, preferring more populated boxes on ties
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(index, colors)| {
+                let (channel, range) = (0..3)
+                    .map(|channel| {
+                        let values = colors.iter().map(|color| color.rgb[channel]);
+                        let range = values.clone().max().unwrap() - values.min().unwrap();
+                        (channel, range)
+                    })
+                    .max_by_key(|(_, range)| *range)
+                    .unwrap();
+                let population: u32 = colors.iter().map(|color| color.count).sum();
+                (index, channel, (range, population))
+            })
+            .max_by_key(|(_, _, key)| *key);
+
+        let Some((index, channel, _)) = widest else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(index);
+        colors.sort_by_key(|color| color.rgb[channel]);
+
+        let half = colors.iter().map(|color| color.count as u64).sum::<u64>() / 2;
+        let mut seen = 0;
+        let median = colors
+            .iter()
+            .position(|color| {
+                seen += color.count as u64;
+                seen >= half
+            })
+            .unwrap_or(0);
+
+        let upper = colors.split_off((median + 1).clamp(1, colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| weighted_average(colors))
+        .collect()
+}
+
+fn kmeans(colors: Vec<Swatch>, limit: usize, seed: u64) -> Vec<Swatch> {
+    const MAX_ITERATIONS: usize = 24;
+
+    let k = limit.min(colors.len());
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let points: Vec<(Lab, f32)> = colors
+        .iter()
+        .map(|color| (Lab::from_rgb(color.rgb), color.count as f32))
+        .collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // k-means++ initialization, weighted by pixel count
+    let first = WeightedIndex::new(points.iter().map(|(_, weight)| *weight))
+        .unwrap()
+        .sample(&mut rng);
+    let mut centers = vec![points[first].0];
+    let mut distances: Vec<f32> = points
+        .iter()
+        .map(|(lab, _)| lab.distance_squared(centers[0]))
+        .collect();
+
+    while centers.len() < k {
+        let weights = points
+            .iter()
+            .zip(&distances)
+            .map(|((_, weight), distance)| weight * distance);
+        let Ok(distribution) = WeightedIndex::new(weights) else {
+            // every color is already a center
+            break;
+        };
+
+        let center = points[distribution.sample(&mut rng)].0;
+        for ((lab, _), distance) in points.iter().zip(distances.iter_mut()) {
+            *distance = distance.min(lab.distance_squared(center));
+        }
+        centers.push(center);
+    }
+
+    let mut assignments = vec![usize::MAX; points.len()];
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+
+        for ((lab, _), assignment) in points.iter().zip(assignments.iter_mut()) {
+            let nearest = centers
+                .iter()
+                .enumerate()
+                .map(|(index, center)| (index, lab.distance_squared(*center)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(index, _)| index)
+                .unwrap();
+
+            if *assignment != nearest {
+                *assignment = nearest;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![(0.0, 0.0, 0.0, 0.0); centers.len()];
+        for ((lab, weight), assignment) in points.iter().zip(&assignments) {
+            let sum = &mut sums[*assignment];
+            sum.0 += lab.l * weight;
+            sum.1 += lab.a * weight;
+            sum.2 += lab.b * weight;
+            sum.3 += weight;
+        }
+
+        for (center, (l, a, b, weight)) in centers.iter_mut().zip(sums) {
+            if weight > 0.0 {
+                *center = Lab {
+                    l: l / weight,
+                    a: a / weight,
+                    b: b / weight,
+                };
+            }
+        }
+    }
+
+    let mut counts = vec![0u32; centers.len()];
+    for (color, assignment) in colors.iter().zip(&assignments) {
+        counts[*assignment] += color.count;
+    }
+
+    centers
+        .into_iter()
+        .zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(center, count)| Swatch {
+            rgb: center.to_rgb(),
+            count,
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    sum: [u64; 3],
+    count: u32,
+    level: usize,
+}
+
+fn octree(colors: Vec<Swatch>, limit: usize) -> Vec<Swatch> {
+    const DEPTH: usize = 8;
+
+    if limit == 0 {
+        return Vec::new();
+    }
+
+    let mut nodes = vec![OctreeNode::default()];
+
+    for color in &colors {
+        let mut node = 0;
+
+        for level in 0..DEPTH {
+            let bit = 7 - level;
+            let octant = (((color.rgb[0] >> bit) & 1) << 2
+                | ((color.rgb[1] >> bit) & 1) << 1
+                | ((color.rgb[2] >> bit) & 1)) as usize;
+
+            node = match nodes[node].children[octant] {
+                Some(child) => child,
+                None => {
+                    nodes.push(OctreeNode {
+                        level: level + 1,
+                        ..Default::default()
+                    });
+                    let child = nodes.len() - 1;
+                    nodes[node].children[octant] = Some(child);
+                    child
+                }
+            };
+        }
+
+        let leaf = &mut nodes[node];
+        for (sum, channel) in leaf.sum.iter_mut().zip(color.rgb) {
+            *sum += channel as u64 * color.count as u64;
+        }
+        leaf.count += color.count;
+    }
+
+    let is_leaf = |node: &OctreeNode| node.children.iter().all(Option::is_none);
+    let mut leaves = nodes.iter().filter(|node| is_leaf(node)).count();
+
+    // fold the least populated nodes of the deepest level into their parents until few enough leaves are left,
+    // once a level is done all nodes one level up only have leaves as children
+    for level in (0..DEPTH).rev() {
+        if leaves <= limit {
+            break;
+        }
+
+        let mut reducible: Vec<(u32, usize)> = nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| node.level == level && !is_leaf(node))
+            .map(|(index, node)| {
+                let population = node
+                    .children
+                    .iter()
+                    .flatten()
+                    .map(|&child| nodes[child].count)
+                    .sum();
+                (population, index)
+            })
+            .collect();
+        reducible.sort_unstable();
+
+        for (_, parent) in reducible {
+            if leaves <= limit {
+                break;
+            }
+
+            let mut children: Vec<(usize, usize)> = nodes[parent]
+                .children
+                .iter()
+                .enumerate()
+                .filter_map(|(octant, child)| Some((octant, (*child)?)))
+                .collect();
+            children.sort_by_key(|(_, child)| nodes[*child].count);
+
+            // folding every child could leave fewer colors than asked for, in that case only the least
+            // populated children are merged into the most populated of them
+            let merged = children.len().min(leaves + 1 - limit);
+            let target = if merged == children.len() {
+                parent
+            } else {
+                children[merged - 1].1
+            };
+
+            for &(octant, child) in &children[..merged] {
+                if child == target {
+                    continue;
+                }
+
+                let (sum, count) = (nodes[child].sum, nodes[child].count);
+                let node = &mut nodes[target];
+                for (total, channel) in node.sum.iter_mut().zip(sum) {
+                    *total += channel;
+                }
+                node.count += count;
+                nodes[parent].children[octant] = None;
+            }
+            leaves = leaves + 1 - merged;
+        }
+    }
+
+    let mut stack = vec![0];
+    let mut swatches = Vec::with_capacity(leaves);
+
+    while let Some(index) = stack.pop() {
+        let node = &nodes[index];
+        if is_leaf(node) {
+            if node.count > 0 {
+                swatches.push(Swatch {
+                    rgb: node.sum.map(|sum| (sum / node.count as u64) as u8),
+                    count: node.count,
+                });
+            }
+        } else {
+            stack.extend(node.children.iter().flatten());
+        }
+    }
+
+    swatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    const ALGORITHMS: [PaletteAlgorithm; 4] = [
+        PaletteAlgorithm::Exact,
+        PaletteAlgorithm::MedianCut,
+        PaletteAlgorithm::Kmeans,
+        PaletteAlgorithm::Octree,
+    ];
+
+    /// Four clusters of slightly varying colors, of 400, 300, 200 and 100 pixels.
+    fn pixels() -> Vec<[u8; 3]> {
+        let mut rng = StdRng::seed_from_u64(7);
+        let clusters = [
+            ([200, 30, 30], 400),
+            ([30, 160, 40], 300),
+            ([40, 60, 220], 200),
+            ([240, 240, 240], 100),
+        ];
+
+        clusters
+            .into_iter()
+            .flat_map(|(rgb, count): ([u8; 3], usize)| vec![rgb; count])
+            .map(|rgb| rgb.map(|channel| channel.saturating_add(rng.gen_range(0..12))))
+            .collect()
+    }
+
+    #[test]
+    fn returns_at_most_limit_swatches_sorted_by_count() {
+        let pixels = pixels();
+
+        for algorithm in ALGORITHMS {
+            for limit in [1, 2, 4, 8, 16] {
+                let palette = algorithm.palette(&pixels, limit, 1);
+
+                assert!(!palette.is_empty(), "{algorithm:?}");
+                assert!(palette.len() <= limit, "{algorithm:?} {limit}");
+                assert!(
+                    palette
+                        .windows(2)
+                        .all(|pair| pair[0].count >= pair[1].count),
+                    "{algorithm:?} {limit}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_pixel_is_counted_once() {
+        let pixels = pixels();
+
+        for algorithm in [
+            PaletteAlgorithm::MedianCut,
+            PaletteAlgorithm::Kmeans,
+            PaletteAlgorithm::Octree,
+        ] {
+            for limit in [1, 2, 4, 8, 16] {
+                let palette = algorithm.palette(&pixels, limit, 1);
+                let total: u32 = palette.iter().map(|swatch| swatch.count).sum();
+                assert_eq!(total as usize, pixels.len(), "{algorithm:?} {limit}");
+            }
+        }
+
+        // exact only adds up if there are no more colors than the limit
+        let palette = PaletteAlgorithm::Exact.palette(&pixels, usize::MAX, 1);
+        let total: u32 = palette.iter().map(|swatch| swatch.count).sum();
+        assert_eq!(total as usize, pixels.len());
+    }
+
+    #[test]
+    fn clustering_finds_the_clusters() {
+        let pixels = pixels();
+
+        // median cut splits at the median pixel rather than between clusters, so it's left out
+        for algorithm in [PaletteAlgorithm::Kmeans, PaletteAlgorithm::Octree] {
+            let counts: Vec<u32> = algorithm
+                .palette(&pixels, 4, 1)
+                .iter()
+                .map(|swatch| swatch.count)
+                .collect();
+            assert_eq!(counts, [400, 300, 200, 100], "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn kmeans_is_deterministic_for_a_seed() {
+        let pixels = pixels();
+
+        for seed in [0, 1, 42] {
+            assert_eq!(
+                PaletteAlgorithm::Kmeans.palette(&pixels, 6, seed),
+                PaletteAlgorithm::Kmeans.palette(&pixels, 6, seed),
+            );
+        }
+    }
+
+    #[test]
+    fn exact_matches_counting_every_color() {
+        let pixels = pixels();
+
+        let mut color_count: HashMap<[u8; 3], u32> = HashMap::new();
+        for rgb in &pixels {
+            *color_count.entry(*rgb).or_insert(0) += 1;
+        }
+        let mut counted: Vec<Swatch> = color_count
+            .into_iter()
+            .map(|(rgb, count)| Swatch { rgb, count })
+            .collect();
+        counted.sort_by_key(|swatch| (Reverse(swatch.count), swatch.rgb));
+
+        assert_eq!(
+            PaletteAlgorithm::Exact.palette(&pixels, usize::MAX, 1),
+            counted
+        );
+        assert_eq!(
+            PaletteAlgorithm::Exact.palette(&pixels, 10, 1),
+            counted[..10]
+        );
+    }
+
+    #[test]
+    fn handles_empty_and_uniform_images() {
+        for algorithm in ALGORITHMS {
+            assert!(algorithm.palette(&[], 4, 1).is_empty(), "{algorithm:?}");
+            assert_eq!(
+                algorithm.palette(&[[10, 20, 30]; 50], 4, 1),
+                [Swatch {
+                    rgb: [10, 20, 30],
+                    count: 50
+                }],
+                "{algorithm:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use image::{Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+use lazy_static::lazy_static;
+use rusttype::{Font, Scale};
+
+lazy_static! {
+    /// DejaVu Sans Mono, see `assets/fonts/LICENSE-DejaVu.txt`.
+    pub static ref FONT: Font<'static> =
+        Font::try_from_bytes(include_bytes!("../../../assets/fonts/DejaVuSansMono.ttf"))
+            .expect("the bundled font is valid");
+}
+
+/// Text smaller than this is unreadable, so it isn't drawn at all.
+const MIN_FONT_SIZE: f32 = 8.0;
+
+/// Draws `lines` centered in the given box, at most `max_size` pixels high and shrunk until every line fits.
+///
+/// Returns `false` if the box is too small for readable text.
+pub fn draw_centered_lines(
+    img: &mut RgbaImage,
+    lines: &[&str],
+    color: Rgba<u8>,
+    (x, y, width, height): (u32, u32, u32, u32),
+    max_size: f32,
+) -> bool {
+    const PADDING: f32 = 4.0;
+
+    let line_count = lines.len().max(1) as f32;
+    let mut size = max_size.min(height as f32 / line_count / 1.25);
+
+    let widest = lines
+        .iter()
+        .map(|line| text_size(Scale::uniform(size), &FONT, line).0)
+        .max()
+        .unwrap_or(0) as f32;
+    let available = width as f32 - 2.0 * PADDING;
+    if widest > available {
+        size *= available / widest;
+    }
+
+    if size < MIN_FONT_SIZE {
+        return false;
+    }
+
+    let scale = Scale::uniform(size);
+    let line_height = size * 1.25;
+    let top = y as f32 + (height as f32 - line_height * line_count) / 2.0;
+
+    for (index, line) in lines.iter().enumerate() {
+        let (line_width, _) = text_size(scale, &FONT, line);
+        let line_x = x as f32 + (width as f32 - line_width as f32) / 2.0;
+        let line_y = top + index as f32 * line_height + (line_height - size) / 2.0;
+
+        draw_text_mut(img, color, line_x as i32, line_y as i32, scale, &FONT, line);
+    }
+
+    true
+}
@@ -0,0 +1,416 @@
+use super::super::{
+    colorspace::{
+        hsl_to_rgb, linear_to_oklab, linear_to_srgb, oklab_to_linear, srgb_to_linear, Hsl,
+    },
+    hex_color::{split_colors, HexColor},
+};
+use crate::error::ApiError;
+use axum::http::StatusCode;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::f32::consts::SQRT_2;
+use utoipa::ToSchema;
+
+/// The most color stops a gradient may have.
+pub const MAX_STOPS: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GradientKind {
+    /// Along a line through the center, see `angle`
+    #[default]
+    Linear,
+    /// From the center to the farthest corner
+    Radial,
+    /// Around the center, starting at `angle`
+    Conic,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Srgb,
+    /// Linear light, which keeps the brightness of mixed colors
+    #[serde(alias = "linear_rgb")]
+    Linear,
+    /// Perceptually even, without muddy midpoints
+    Oklab,
+    /// Along the shorter way around the hue circle
+    Hsl,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ColorStop {
+    pub color: HexColor,
+    /// From 0 (start) to 1 (end), may lie outside of that range.
+    pub position: f32,
+}
+
+/// Parses comma separated color stops like `red, #00f 30%, hsl(120 100% 50%)`.
+///
+/// Missing positions are filled in like CSS does: the first and last stop default to 0% and 100%, the others are
+/// spread evenly between their neighbours and no stop may come before the one preceding it.
+pub fn parse_stops(input: &str) -> Result<Vec<ColorStop>, ApiError> {
+    let stops = split_colors(input)
+        .into_iter()
+        .map(|stop| {
+            let stop = stop.trim();
+
+            match stop.rsplit_once(char::is_whitespace) {
+                Some((color, position)) if position.ends_with('%') => {
+                    let percentage = position
+                        .trim_end_matches('%')
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|percentage| percentage.is_finite())
+                        .ok_or_else(|| {
+                            ApiError::Any(
+                                StatusCode::BAD_REQUEST,
+                                format!("`{position}` is not a valid stop position."),
+                            )
+                        })?;
+
+                    Ok((color.parse::<HexColor>()?, Some(percentage / 100.0)))
+                }
+                _ => Ok((stop.parse::<HexColor>()?, None)),
+            }
+        })
+        .collect::<Result<Vec<_>, ApiError>>()?;
+
+    if !(2..=MAX_STOPS).contains(&stops.len()) {
+        return Err(ApiError::Any(
+            StatusCode::BAD_REQUEST,
+            format!("A gradient needs between 2 and {MAX_STOPS} color stops."),
+        ));
+    }
+
+    let mut positions: Vec<Option<f32>> = stops.iter().map(|(_, position)| *position).collect();
+    let last = positions.len() - 1;
+    positions[0] = positions[0].or(Some(0.0));
+    positions[last] = positions[last].or(Some(1.0));
+
+    let mut max = f32::MIN;
+    for position in positions.iter_mut().flatten() {
+        max = max.max(*position);
+        *position = max;
+    }
+
+    let mut start = 0;
+    for end in 1..positions.len() {
+        if let Some(to) = positions[end] {
+            let from = positions[start].unwrap();
+            let gap = (end - start) as f32;
+
+            for (step, position) in positions[start + 1..end].iter_mut().enumerate() {
+                *position = Some(from + (to - from) * (step + 1) as f32 / gap);
+            }
+            start = end;
+        }
+    }
+
+    Ok(stops
+        .into_iter()
+        .zip(positions)
+        .map(|((color, _), position)| ColorStop {
+            color,
+            position: position.unwrap(),
+        })
+        .collect())
+}
+
+/// A color stop converted to the interpolation color space, with a separate alpha.
+struct SpaceStop {
+    components: [f32; 3],
+    alpha: f32,
+    position: f32,
+}
+
+impl Interpolation {
+    fn components(self, color: HexColor) -> [f32; 3] {
+        let rgb = [color.red, color.green, color.blue];
+
+        match self {
+            Interpolation::Srgb => rgb.map(|channel| channel as f32 / 255.0),
+            Interpolation::Linear => rgb.map(srgb_to_linear),
+            Interpolation::Oklab => linear_to_oklab(rgb.map(srgb_to_linear)),
+            Interpolation::Hsl => {
+                let Hsl { h, s, l } = Hsl::from_rgb(rgb);
+                [h, s / 100.0, l / 100.0]
+            }
+        }
+    }
+
+    fn to_rgb(self, components: [f32; 3]) -> [u8; 3] {
+        let to_u8 = |channel: f32| (channel.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        match self {
+            Interpolation::Srgb => components.map(to_u8),
+            Interpolation::Linear => components.map(linear_to_srgb),
+            Interpolation::Oklab => oklab_to_linear(components).map(linear_to_srgb),
+            Interpolation::Hsl => {
+                let [h, s, l] = components;
+                hsl_to_rgb(h, s, l).map(to_u8)
+            }
+        }
+    }
+
+    /// Mixes two stops with premultiplied alpha, like CSS does, so transparent stops don't tint their neighbours.
+    fn mix(self, from: &SpaceStop, to: &SpaceStop, t: f32) -> [u8; 4] {
+        let alpha = from.alpha + (to.alpha - from.alpha) * t;
+        let mut components = [0.0; 3];
+
+        for (index, component) in components.iter_mut().enumerate() {
+            let (a, b) = (from.components[index], to.components[index]);
+
+            *component = if self == Interpolation::Hsl && index == 0 {
+                // gray has no hue, so it takes the hue of the other stop
+                let a = if from.components[1] == 0.0 { b } else { a };
+                let b = if to.components[1] == 0.0 { a } else { b };
+                a + ((b - a + 540.0) % 360.0 - 180.0) * t
+            } else if alpha > 0.0 {
+                (a * from.alpha * (1.0 - t) + b * to.alpha * t) / alpha
+            } else {
+                a + (b - a) * t
+            };
+        }
+
+        let [red, green, blue] = self.to_rgb(components);
+        [red, green, blue, (alpha * 255.0).round() as u8]
+    }
+}
+
+pub fn render(
+    stops: &[ColorStop],
+    kind: GradientKind,
+    angle: f32,
+    interpolation: Interpolation,
+    (width, height): (u32, u32),
+) -> RgbaImage {
+    // colors are looked up from a table, converting every pixel between color spaces would be a lot slower
+    const LUT_SIZE: usize = 1024;
+
+    let stops: Vec<SpaceStop> = stops
+        .iter()
+        .map(|stop| SpaceStop {
+            components: interpolation.components(stop.color),
+            alpha: stop.color.alpha as f32 / 255.0,
+            position: stop.position,
+        })
+        .collect();
+
+    let lut: Vec<Rgba<u8>> = (0..LUT_SIZE)
+        .map(|index| {
+            let t = index as f32 / (LUT_SIZE - 1) as f32;
+            Rgba(color_at(&stops, t, interpolation))
+        })
+        .collect();
+
+    let (center_x, center_y) = (width as f32 / 2.0, height as f32 / 2.0);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let line_length = (width as f32 * sin).abs() + (height as f32 * cos).abs();
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let dx = x as f32 + 0.5 - center_x;
+        let dy = y as f32 + 0.5 - center_y;
+
+        let t = match kind {
+            // 0deg points up and 90deg to the right, like in CSS
+            GradientKind::Linear => (dx * sin - dy * cos) / line_length + 0.5,
+            GradientKind::Radial => (dx / (center_x * SQRT_2)).hypot(dy / (center_y * SQRT_2)),
+            GradientKind::Conic => (dx.atan2(-dy).to_degrees() - angle).rem_euclid(360.0) / 360.0,
+        };
+
+        lut[(t.clamp(0.0, 1.0) * (LUT_SIZE - 1) as f32).round() as usize]
+    })
+}
+
+fn color_at(stops: &[SpaceStop], t: f32, interpolation: Interpolation) -> [u8; 4] {
+    let first = &stops[0];
+    let last = &stops[stops.len() - 1];
+
+    if t <= first.position {
+        return interpolation.mix(first, first, 0.0);
+    }
+
+    stops
+        .windows(2)
+        .find(|pair| t < pair[1].position)
+        .map(|pair| {
+            let (from, to) = (&pair[0], &pair[1]);
+            let local = (t - from.position) / (to.position - from.position);
+            interpolation.mix(from, to, local)
+        })
+        .unwrap_or_else(|| interpolation.mix(last, last, 0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+
+    const INTERPOLATIONS: [Interpolation; 4] = [
+        Interpolation::Srgb,
+        Interpolation::Linear,
+        Interpolation::Oklab,
+        Interpolation::Hsl,
+    ];
+
+    fn positions(input: &str) -> Vec<f32> {
+        parse_stops(input)
+            .unwrap()
+            .iter()
+            .map(|stop| (stop.position * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    fn assert_close(actual: Rgba<u8>, expected: [u8; 4], context: &str) {
+        let close = actual
+            .0
+            .iter()
+            .zip(expected)
+            .all(|(actual, expected)| actual.abs_diff(expected) <= 2);
+        assert!(close, "{context}: {actual:?} isn't {expected:?}");
+    }
+
+    #[test]
+    fn spreads_missing_positions_evenly() {
+        assert_eq!(positions("red, blue"), [0.0, 1.0]);
+        assert_eq!(positions("red, lime, blue"), [0.0, 0.5, 1.0]);
+        assert_eq!(positions("red, lime, teal, blue"), [0.0, 0.333, 0.667, 1.0]);
+        assert_eq!(positions("red 20%, lime, blue 80%"), [0.2, 0.5, 0.8]);
+        assert_eq!(positions("red, lime 60%, teal, blue"), [0.0, 0.6, 0.8, 1.0]);
+        // positions outside of 0-100% are kept
+        assert_eq!(positions("red -50%, blue 150%"), [-0.5, 1.5]);
+    }
+
+    #[test]
+    fn clamps_positions_that_go_backwards() {
+        assert_eq!(positions("red 50%, lime 20%, blue"), [0.5, 0.5, 1.0]);
+        assert_eq!(
+            positions("red, lime 70%, teal 30%, blue 10%"),
+            [0.0, 0.7, 0.7, 0.7]
+        );
+        // a missing position is spread between the clamped neighbours
+        assert_eq!(positions("red 60%, lime, blue 40%"), [0.6, 0.6, 0.6]);
+    }
+
+    #[test]
+    fn parses_stops_with_functions_and_alpha() {
+        let stops = parse_stops("rgb(255, 0, 0) 10%, hsl(240 100% 50% / 50%)").unwrap();
+
+        assert_eq!(stops[0].color.rgba(), [255, 0, 0, 255]);
+        assert_eq!(stops[1].color.rgba(), [0, 0, 255, 128]);
+        assert_eq!(stops[1].position, 1.0);
+    }
+
+    #[test]
+    fn rejects_invalid_stops() {
+        let is_bad_request = |input: &str| match parse_stops(input) {
+            Err(err) => err.into_response().status() == StatusCode::BAD_REQUEST,
+            Ok(_) => false,
+        };
+
+        assert!(is_bad_request("red"));
+        assert!(is_bad_request(&vec!["red"; MAX_STOPS + 1].join(", ")));
+        assert!(parse_stops(&vec!["red"; MAX_STOPS].join(", ")).is_ok());
+        assert!(is_bad_request("red, nope"));
+        assert!(is_bad_request("red, blue x%"));
+        assert!(is_bad_request("red, blue inf%"));
+    }
+
+    #[test]
+    fn every_shape_starts_and_ends_at_the_outer_stops() {
+        // the outer pixels lie half a pixel inside the gradient, so the outer stops are repeated a bit further in
+        let stops =
+            parse_stops("#ff0000, #ff0000 2%, #00ff0080 50%, #0000ff 98%, #0000ff").unwrap();
+        let (first, last) = ([255, 0, 0, 255], [0, 0, 255, 255]);
+
+        for interpolation in INTERPOLATIONS {
+            let linear = render(&stops, GradientKind::Linear, 90.0, interpolation, (256, 16));
+            assert_close(linear[(0, 8)], first, &format!("linear {interpolation:?}"));
+            assert_close(linear[(255, 8)], last, &format!("linear {interpolation:?}"));
+
+            let vertical = render(
+                &stops,
+                GradientKind::Linear,
+                180.0,
+                interpolation,
+                (16, 256),
+            );
+            assert_close(
+                vertical[(8, 0)],
+                first,
+                &format!("180deg {interpolation:?}"),
+            );
+            assert_close(
+                vertical[(8, 255)],
+                last,
+                &format!("180deg {interpolation:?}"),
+            );
+
+            let radial = render(&stops, GradientKind::Radial, 0.0, interpolation, (128, 128));
+            assert_close(
+                radial[(64, 64)],
+                first,
+                &format!("radial {interpolation:?}"),
+            );
+            assert_close(radial[(0, 0)], last, &format!("radial {interpolation:?}"));
+            assert_close(
+                radial[(127, 127)],
+                last,
+                &format!("radial {interpolation:?}"),
+            );
+
+            // conic gradients start clockwise of `angle` and end just before it
+            let conic = render(&stops, GradientKind::Conic, 0.0, interpolation, (128, 128));
+            assert_close(conic[(64, 0)], first, &format!("conic {interpolation:?}"));
+            assert_close(conic[(63, 0)], last, &format!("conic {interpolation:?}"));
+        }
+    }
+
+    #[test]
+    fn interpolates_in_the_requested_space() {
+        let stops = parse_stops("#ff0000, #00ff00").unwrap();
+        let middle = |interpolation: Interpolation| {
+            let stops: Vec<SpaceStop> = stops
+                .iter()
+                .map(|stop| SpaceStop {
+                    components: interpolation.components(stop.color),
+                    alpha: 1.0,
+                    position: stop.position,
+                })
+                .collect();
+            color_at(&stops, 0.5, interpolation)
+        };
+
+        assert_eq!(middle(Interpolation::Srgb), [128, 128, 0, 255]);
+        // linear light mixes to a brighter color than sRGB
+        assert_eq!(middle(Interpolation::Linear), [188, 188, 0, 255]);
+        // halfway between 0deg and 120deg is yellow
+        assert_eq!(middle(Interpolation::Hsl), [255, 255, 0, 255]);
+
+        let [red, green, blue, _] = middle(Interpolation::Oklab);
+        assert!(
+            red > 128 && green > 128 && blue < 64,
+            "{red} {green} {blue}"
+        );
+        assert_ne!(middle(Interpolation::Oklab), middle(Interpolation::Linear));
+    }
+
+    #[test]
+    fn transparent_stops_dont_tint_their_neighbours() {
+        let stops = parse_stops("#ff0000, #0000ff00").unwrap();
+        let img = render(
+            &stops,
+            GradientKind::Linear,
+            90.0,
+            Interpolation::Srgb,
+            (101, 1),
+        );
+
+        let [red, green, blue, alpha] = img[(50, 0)].0;
+        assert_eq!((red, green, blue), (255, 0, 0));
+        assert!((125..=130).contains(&alpha), "{alpha}");
+    }
+}
@@ -0,0 +1,74 @@
+pub(super) mod logic;
+
+use super::{
+    output::{EncodedImage, ImageOutput, OutputQueryParams},
+    preview_color::preview_dimensions,
+};
+use crate::{error::ApiError, extract::Query, state::AppState};
+use axum::{extract::State, http::StatusCode};
+use image::DynamicImage;
+use logic::{parse_stops, render, GradientKind, Interpolation};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GradientQueryParams {
+    /// Comma separated colors, each optionally followed by its position, e.g. `red, #0000ff 30%, hsl(120 100% 50%)`
+    stops: String,
+
+    #[serde(default)]
+    #[param(inline)]
+    kind: GradientKind,
+
+    /// The direction of a linear gradient (0 points up, defaults to 180) or the start of a conic one (defaults to 0), in degrees
+    angle: Option<f32>,
+
+    #[serde(default)]
+    #[param(inline)]
+    interpolation: Interpolation,
+
+    /// The width in pixels, defaults to `height` if only that is set
+    #[param(minimum = 1, default = 256)]
+    width: Option<u32>,
+
+    /// The height in pixels, defaults to `width` if only that is set
+    #[param(minimum = 1, default = 256)]
+    height: Option<u32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/gradient",
+    params(GradientQueryParams, OutputQueryParams),
+    responses(
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
+    )
+)]
+pub async fn gradient(
+    State(state): State<AppState>,
+    Query(params): Query<GradientQueryParams>,
+    output: ImageOutput,
+) -> Result<EncodedImage, ApiError> {
+    let stops = parse_stops(&params.stops)?;
+    let dimensions = preview_dimensions(params.width, params.height, (256, 256))?;
+
+    let angle = params.angle.unwrap_or(match params.kind {
+        GradientKind::Linear => 180.0,
+        GradientKind::Radial | GradientKind::Conic => 0.0,
+    });
+    if !angle.is_finite() {
+        return Err(ApiError::AnyStatic(
+            StatusCode::BAD_REQUEST,
+            "`angle` must be a finite number.",
+        ));
+    }
+
+    state
+        .workers
+        .run(move || {
+            let img = render(&stops, params.kind, angle, params.interpolation, dimensions);
+            output.encode(DynamicImage::ImageRgba8(img))
+        })
+        .await?
+}
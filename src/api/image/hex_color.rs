@@ -1,10 +1,31 @@
-use super::preview_color::preview_size::PreviewSize;
-use image::{ImageBuffer, Rgb};
+use super::colorspace::hsl_to_rgb;
+use crate::{error::ApiError, utils::rgb_to_hex};
+use image::{ImageBuffer, Rgba, RgbaImage};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, str::FromStr};
+use thiserror::Error;
 
+/// A color with an alpha channel, parsed from any of the formats CSS understands.
+///
+/// Accepts 3, 4, 6 or 8 digit hex (with or without `#`), `rgb()`/`rgba()`, `hsl()`/`hsla()`, `hwb()`, CSS named
+/// colors and the names of `color_names`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct HexColor {
-    red: u8,
-    green: u8,
-    blue: u8,
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+#[derive(Debug, Error)]
+#[error("`{0}` is not a valid color.")]
+pub struct ParseColorError(pub String);
+
+impl From<ParseColorError> for ApiError {
+    fn from(err: ParseColorError) -> Self {
+        ApiError::InvalidColor(err.to_string())
+    }
 }
 
 impl From<u32> for HexColor {
@@ -13,29 +34,538 @@ impl From<u32> for HexColor {
         let green = ((value & 0x00ff00) >> 8) as u8;
         let blue = (value & 0x0000ff) as u8;
 
-        Self { red, green, blue }
+        Self::opaque([red, green, blue])
+    }
+}
+
+impl FromStr for HexColor {
+    type Err = ParseColorError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let color = input.trim().to_lowercase();
+
+        let parsed = if let Some(hex) = color.strip_prefix('#') {
+            parse_hex(hex)
+        } else if let Some((function, args)) = color
+            .strip_suffix(')')
+            .and_then(|color| color.split_once('('))
+        {
+            parse_function(function.trim(), args)
+        } else {
+            parse_hex(&color).or_else(|| parse_name(&color))
+        };
+
+        parsed.ok_or_else(|| ParseColorError(input.to_owned()))
+    }
+}
+
+impl<'de> Deserialize<'de> for HexColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let color = String::deserialize(deserializer)?;
+        color.parse().map_err(serde::de::Error::custom)
     }
 }
 
-impl From<String> for HexColor {
-    fn from(mut hex: String) -> Self {
-        hex = hex.replace('#', "");
+/// Splits a comma separated list of colors, ignoring the commas inside of `rgb()` and the like.
+pub fn split_colors(input: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
 
-        let number = u32::from_str_radix(&hex, 16).unwrap_or(0);
-        HexColor::from(number)
+    for (index, char) in input.char_indices() {
+        match char {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(&input[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
     }
+
+    parts.push(&input[start..]);
+    parts
 }
 
 impl HexColor {
-    pub fn into_preview(self, prev_size: PreviewSize) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-        let (width, height) = prev_size.into();
-        let mut img = ImageBuffer::new(width, height);
+    pub const fn opaque([red, green, blue]: [u8; 3]) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            alpha: 255,
+        }
+    }
+
+    pub fn rgb(self) -> [u8; 3] {
+        [self.red, self.green, self.blue]
+    }
+
+    /// Formats the color as `#rrggbb`, or `#rrggbbaa` if it's not opaque.
+    pub fn to_hex(self) -> String {
+        match self.alpha {
+            255 => rgb_to_hex(&self.rgb()),
+            alpha => format!("{}{alpha:02x}", rgb_to_hex(&self.rgb())),
+        }
+    }
+
+    pub fn rgba(self) -> [u8; 4] {
+        [self.red, self.green, self.blue, self.alpha]
+    }
+
+    /// Composites this color on top of `background` ("source over").
+    pub fn over(self, background: HexColor) -> HexColor {
+        let alpha = self.alpha as f32 / 255.0;
+        let background_alpha = background.alpha as f32 / 255.0 * (1.0 - alpha);
+        let out_alpha = alpha + background_alpha;
+
+        if out_alpha == 0.0 {
+            return HexColor { alpha: 0, ..self };
+        }
+
+        let blend = |channel: u8, background: u8| {
+            ((channel as f32 * alpha + background as f32 * background_alpha) / out_alpha).round()
+                as u8
+        };
+
+        HexColor {
+            red: blend(self.red, background.red),
+            green: blend(self.green, background.green),
+            blue: blend(self.blue, background.blue),
+            alpha: (out_alpha * 255.0).round() as u8,
+        }
+    }
+
+    pub fn into_preview(
+        self,
+        (width, height): (u32, u32),
+        checkerboard: Option<Checkerboard>,
+    ) -> RgbaImage {
+        match checkerboard {
+            Some(checkerboard) => {
+                let on_light = Rgba(self.over(checkerboard.light).rgba());
+                let on_dark = Rgba(self.over(checkerboard.dark).rgba());
+
+                ImageBuffer::from_fn(width, height, |x, y| {
+                    if (x / checkerboard.size + y / checkerboard.size) % 2 == 0 {
+                        on_light
+                    } else {
+                        on_dark
+                    }
+                })
+            }
+            // Fill the image with the specified color
+            None => ImageBuffer::from_pixel(width, height, Rgba(self.rgba())),
+        }
+    }
+}
+
+/// A checkerboard background that makes transparency visible.
+#[derive(Debug, Clone, Copy)]
+pub struct Checkerboard {
+    /// The size of a square in pixels, never 0.
+    pub size: u32,
+    pub light: HexColor,
+    pub dark: HexColor,
+}
+
+fn parse_hex(hex: &str) -> Option<HexColor> {
+    if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let digit = |index: usize| u8::from_str_radix(hex.get(index..=index)?, 16).ok();
+    let pair = |index: usize| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok();
+
+    let [red, green, blue, alpha] = match hex.len() {
+        3 | 4 => [digit(0)?, digit(1)?, digit(2)?, digit(3).unwrap_or(15)].map(|digit| digit * 17),
+        6 | 8 => [pair(0)?, pair(2)?, pair(4)?, pair(6).unwrap_or(255)],
+        _ => return None,
+    };
+
+    Some(HexColor {
+        red,
+        green,
+        blue,
+        alpha,
+    })
+}
+
+/// Parses the arguments of `rgb()`, `rgba()`, `hsl()`, `hsla()` and `hwb()`, in both the comma separated and the
+/// space separated (`rgb(255 0 0 / 50%)`) syntax.
+fn parse_function(function: &str, args: &str) -> Option<HexColor> {
+    let (args, alpha) = match args.split_once('/') {
+        Some((args, alpha)) => (args, Some(alpha.trim())),
+        None => (args, None),
+    };
+
+    let mut args: Vec<&str> = if args.contains(',') {
+        args.split(',').map(str::trim).collect()
+    } else {
+        args.split_whitespace().collect()
+    };
+
+    let alpha = match (alpha, args.len()) {
+        (Some(alpha), 3) => alpha,
+        (None, 4) => args.pop()?,
+        (None, 3) => "1",
+        _ => return None,
+    };
+    let alpha = (parse_fraction(alpha, 1.0)? * 255.0).round() as u8;
+
+    let rgb = match function {
+        "rgb" | "rgba" => {
+            let channel = |arg: &str| parse_fraction(arg, 255.0);
+            [channel(args[0])?, channel(args[1])?, channel(args[2])?]
+        }
+        "hsl" | "hsla" => hsl_to_rgb(
+            parse_hue(args[0])?,
+            parse_percentage(args[1])?,
+            parse_percentage(args[2])?,
+        ),
+        "hwb" => {
+            // the hue is checked even if it doesn't matter, so a typo doesn't give a gray
+            let hue = parse_hue(args[0])?;
+            let (whiteness, blackness) = (parse_percentage(args[1])?, parse_percentage(args[2])?);
 
-        // Fill the image with the specified color
-        for (_, _, pixel) in img.enumerate_pixels_mut() {
-            *pixel = Rgb([self.red, self.green, self.blue]);
+            if whiteness + blackness >= 1.0 {
+                [whiteness / (whiteness + blackness); 3]
+            } else {
+                hsl_to_rgb(hue, 1.0, 0.5)
+                    .map(|channel| channel * (1.0 - whiteness - blackness) + whiteness)
+            }
         }
+        _ => return None,
+    };
+
+    let [red, green, blue] = rgb.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+
+    Some(HexColor {
+        red,
+        green,
+        blue,
+        alpha,
+    })
+}
+
+/// Parses a percentage or a number relative to `max` as 0-1, anything out of range is rejected.
+fn parse_fraction(arg: &str, max: f32) -> Option<f32> {
+    let value = match arg.strip_suffix('%') {
+        Some(percentage) => percentage.parse::<f32>().ok()? / 100.0,
+        None => arg.parse::<f32>().ok()? / max,
+    };
+
+    (0.0..=1.0).contains(&value).then_some(value)
+}
+
+/// Parses a percentage (the `%` is optional, like in CSS Color 4) as 0-1.
+fn parse_percentage(arg: &str) -> Option<f32> {
+    parse_fraction(arg.strip_suffix('%').unwrap_or(arg), 100.0)
+}
+
+/// Parses a hue in degrees, `deg`, `rad`, `grad` or `turn`.
+fn parse_hue(arg: &str) -> Option<f32> {
+    let units = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / std::f32::consts::PI),
+        ("turn", 360.0),
+    ];
+
+    let (number, factor) = units
+        .iter()
+        .find_map(|(unit, factor)| Some((arg.strip_suffix(unit)?, *factor)))
+        .unwrap_or((arg, 1.0));
+
+    let hue = number.parse::<f32>().ok()? * factor;
+    hue.is_finite().then_some(hue)
+}
+
+lazy_static! {
+    /// The names of `color_names`, lowercased so they can be looked up case-insensitively.
+    static ref COLOR_NAMES: HashMap<String, [u8; 3]> = color_names::COLOR_MAP
+        .entries()
+        .map(|(rgb, name)| (name.to_lowercase(), *rgb))
+        .collect();
+}
+
+fn parse_name(name: &str) -> Option<HexColor> {
+    if name == "transparent" {
+        return Some(HexColor {
+            alpha: 0,
+            ..HexColor::opaque([0, 0, 0])
+        });
+    }
+
+    CSS_COLORS
+        .iter()
+        .find(|(css_name, _)| *css_name == name)
+        .map(|(_, rgb)| *rgb)
+        .or_else(|| COLOR_NAMES.get(name).copied())
+        .map(HexColor::opaque)
+}
+
+/// The named colors of CSS Color 4.
+const CSS_COLORS: [(&str, [u8; 3]); 148] = [
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    fn rgba(input: &str) -> [u8; 4] {
+        input
+            .parse::<HexColor>()
+            .unwrap_or_else(|err| panic!("{err}"))
+            .rgba()
+    }
+
+    #[test]
+    fn parses_every_format() {
+        let cases = [
+            ("#f80", [255, 136, 0, 255]),
+            ("f80", [255, 136, 0, 255]),
+            ("#f808", [255, 136, 0, 136]),
+            ("#ff8800", [255, 136, 0, 255]),
+            ("FF8800", [255, 136, 0, 255]),
+            ("#ff880080", [255, 136, 0, 128]),
+            ("rgb(255, 136, 0)", [255, 136, 0, 255]),
+            ("rgb(255 136 0)", [255, 136, 0, 255]),
+            ("rgb(100% 0% 50%)", [255, 0, 128, 255]),
+            ("rgba(255, 136, 0, 0.5)", [255, 136, 0, 128]),
+            ("rgb(255 136 0 / 50%)", [255, 136, 0, 128]),
+            ("hsl(120, 100%, 50%)", [0, 255, 0, 255]),
+            ("hsl(0.5turn 100% 25%)", [0, 128, 128, 255]),
+            ("hsla(240deg, 100%, 50%, 0.25)", [0, 0, 255, 64]),
+            ("hsl(240 100 50 / 25%)", [0, 0, 255, 64]),
+            ("hwb(0 0% 0%)", [255, 0, 0, 255]),
+            ("hwb(120 20% 20%)", [51, 204, 51, 255]),
+            ("hwb(90 60% 60%)", [128, 128, 128, 255]),
+            ("rebeccapurple", [102, 51, 153, 255]),
+            ("  Teal ", [0, 128, 128, 255]),
+            ("transparent", [0, 0, 0, 0]),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(rgba(input), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn parses_the_names_of_color_names() {
+        let (rgb, name) = color_names::COLOR_MAP
+            .entries()
+            .find(|(_, name)| name.contains(' '))
+            .unwrap();
+
+        assert_eq!(rgba(name), [rgb[0], rgb[1], rgb[2], 255], "{name}");
+        assert_eq!(rgba(&name.to_uppercase())[..3], rgb[..], "{name}");
+    }
+
+    #[test]
+    fn rejects_invalid_colors() {
+        let invalid = [
+            "",
+            "zzz",
+            "#12345",
+            "#1234567",
+            "#ggg",
+            "12",
+            "rgb(256, 0, 0)",
+            "rgb(-1 0 0)",
+            "rgb(0 0 0 / 2)",
+            "rgb(0 0 0 / 101%)",
+            "hsl(0 101% 50%)",
+            "hwb(0 -10% 0%)",
+            "rgb(0, 0)",
+            "rgb(0, 0, 0, 0, 0)",
+            "rgb(0 0 0 0 / 1)",
+            "hsl(nope 100% 50%)",
+            "hwb(garbage 60% 60%)",
+            "rgb(nan 0 0)",
+            "cmyk(0 0 0 0)",
+            "notacolor",
+        ];
+
+        for input in invalid {
+            assert!(input.parse::<HexColor>().is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn names_the_invalid_input_in_the_error() {
+        let err = ApiError::from("hwb(garbage 60% 60%)".parse::<HexColor>().unwrap_err());
+        assert_eq!(
+            err.to_string(),
+            "`hwb(garbage 60% 60%)` is not a valid color."
+        );
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn splits_colors_outside_of_functions() {
+        assert_eq!(
+            split_colors("#fff, rgb(0, 0, 0),teal"),
+            ["#fff", " rgb(0, 0, 0)", "teal"]
+        );
+    }
 
-        img
+    #[test]
+    fn formats_hex_with_alpha_only_if_needed() {
+        assert_eq!(HexColor::opaque([255, 136, 0]).to_hex(), "#ff8800");
+        assert_eq!(
+            "#ff880080".parse::<HexColor>().unwrap().to_hex(),
+            "#ff880080"
+        );
     }
 }
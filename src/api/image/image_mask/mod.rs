@@ -0,0 +1,415 @@
+mod path;
+mod raster;
+mod shape;
+
+use super::{
+    output::{EncodedImage, ImageOutput, OutputQueryParams},
+    source::{ImageUploadForm, ImageUrlQueryParams},
+};
+use crate::{
+    animation::animation_from_bytes,
+    error::ApiError,
+    extract::{ImageUpload, Query},
+    fetch::fetch_raw_image,
+    state::AppState,
+};
+use axum::{body::Bytes, extract::State, http::StatusCode};
+use image::RgbaImage;
+pub use raster::FillRule;
+use raster::Point;
+use serde::Deserialize;
+use std::result::Result as StdResult;
+use utoipa::{IntoParams, ToSchema};
+
+/// The longest SVG path that is accepted.
+const MAX_PATH_LENGTH: usize = 16 * 1024;
+const MAX_CORNERS: u32 = 256;
+
+mod defaults {
+    #[inline(always)]
+    pub fn exponent() -> f64 {
+        5.0
+    }
+
+    #[inline(always)]
+    pub fn sides() -> u32 {
+        5
+    }
+
+    #[inline(always)]
+    pub fn points() -> u32 {
+        5
+    }
+
+    #[inline(always)]
+    pub fn inner_radius() -> f64 {
+        0.5
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskShape {
+    /// A superellipse over the whole image, see `exponent`
+    Squircle,
+    Circle,
+    Hexagon,
+    /// See `points` and `inner_radius`
+    Star,
+    Heart,
+    /// A regular polygon, see `sides`
+    Polygon,
+    /// The SVG path given in `path`
+    Path,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MaskQueryParams {
+    /// Everything outside of the shape becomes transparent. Every shape but `squircle` and `path` keeps its proportions and is centered
+    #[param(inline)]
+    shape: MaskShape,
+
+    /// How square the `squircle` is, `2` is an ellipse and `4` to `5` looks like an app icon
+    #[serde(default = "defaults::exponent")]
+    #[param(minimum = 0.1, maximum = 100.0, default = 5.0)]
+    exponent: f64,
+
+    /// The number of sides of the `polygon`
+    #[serde(default = "defaults::sides")]
+    #[param(minimum = 3, maximum = 256, default = 5)]
+    sides: u32,
+
+    /// The number of tips of the `star`
+    #[serde(default = "defaults::points")]
+    #[param(minimum = 2, maximum = 256, default = 5)]
+    points: u32,
+
+    /// How far the inner corners of the `star` are from its center, relative to its tips
+    #[serde(default = "defaults::inner_radius")]
+    #[param(minimum = 0.0, maximum = 1.0, default = 0.5)]
+    inner_radius: f64,
+
+    /// Turns the `hexagon`, `star` and `polygon` clockwise, in degrees
+    #[serde(default)]
+    rotation: f64,
+
+    /// SVG path data like `M 0 0 L 10 0 L 5 10 Z`, in pixels unless `viewbox` is set
+    path: Option<String>,
+
+    /// The area of the `path` that is fit into the image as `min-x min-y width height`, like SVG's `viewBox`
+    viewbox: Option<String>,
+
+    /// How overlapping parts of the `path` are filled
+    #[serde(default)]
+    #[param(inline)]
+    fill_rule: FillRule,
+}
+
+impl MaskQueryParams {
+    /// The outlines of the shape, in pixels of an image with the given size.
+    fn outlines(&self, width: u32, height: u32) -> Result<Vec<Vec<Point>>, ApiError> {
+        let bad_request =
+            |message: &'static str| ApiError::AnyStatic(StatusCode::BAD_REQUEST, message);
+        let check_rotation = || {
+            self.rotation
+                .is_finite()
+                .then_some(self.rotation)
+                .ok_or_else(|| bad_request("`rotation` must be a finite number."))
+        };
+
+        let outline = match self.shape {
+            MaskShape::Squircle => {
+                if !(0.1..=100.0).contains(&self.exponent) {
+                    return Err(bad_request("`exponent` must be between 0.1 and 100."));
+                }
+                shape::superellipse(width, height, self.exponent)
+            }
+            MaskShape::Circle => shape::circle(width, height),
+            MaskShape::Hexagon => shape::polygon(width, height, 6, check_rotation()?),
+            MaskShape::Polygon => {
+                if !(3..=MAX_CORNERS).contains(&self.sides) {
+                    return Err(bad_request("`sides` must be between 3 and 256."));
+                }
+                shape::polygon(width, height, self.sides, check_rotation()?)
+            }
+            MaskShape::Star => {
+                if !(2..=MAX_CORNERS).contains(&self.points) {
+                    return Err(bad_request("`points` must be between 2 and 256."));
+                }
+                if !(0.0..=1.0).contains(&self.inner_radius) {
+                    return Err(bad_request("`inner_radius` must be between 0 and 1."));
+                }
+                shape::star(
+                    width,
+                    height,
+                    self.points,
+                    self.inner_radius,
+                    check_rotation()?,
+                )
+            }
+            MaskShape::Heart => shape::heart(width, height),
+            MaskShape::Path => return self.path_outlines(width, height),
+        };
+
+        Ok(vec![outline])
+    }
+
+    fn path_outlines(&self, width: u32, height: u32) -> Result<Vec<Vec<Point>>, ApiError> {
+        let data = self.path.as_deref().ok_or(ApiError::AnyStatic(
+            StatusCode::BAD_REQUEST,
+            "`path` is required for the `path` shape.",
+        ))?;
+        if data.len() > MAX_PATH_LENGTH {
+            return Err(ApiError::Any(
+                StatusCode::BAD_REQUEST,
+                format!("`path` can be at most {MAX_PATH_LENGTH} bytes long."),
+            ));
+        }
+
+        let outlines = path::parse(data).map_err(|reason| {
+            ApiError::Any(
+                StatusCode::BAD_REQUEST,
+                format!("`path` is not a valid SVG path, {reason}."),
+            )
+        })?;
+
+        let Some(viewbox) = &self.viewbox else {
+            return Ok(outlines);
+        };
+
+        let viewbox: Vec<f64> = viewbox
+            .split(|char: char| char == ',' || char.is_whitespace())
+            .filter(|part| !part.is_empty())
+            .map(str::parse)
+            .collect::<StdResult<_, _>>()
+            .ok()
+            .filter(|viewbox: &Vec<f64>| {
+                viewbox.len() == 4
+                    && viewbox.iter().all(|value| value.is_finite())
+                    && viewbox[2] > 0.0
+                    && viewbox[3] > 0.0
+            })
+            .ok_or(ApiError::AnyStatic(
+                StatusCode::BAD_REQUEST,
+                "`viewbox` must be `min-x min-y width height` with a positive width and height.",
+            ))?;
+
+        // Like SVG's default `preserveAspectRatio="xMidYMid meet"`.
+        let [min_x, min_y, view_width, view_height] =
+            [viewbox[0], viewbox[1], viewbox[2], viewbox[3]];
+        let scale = (width as f64 / view_width).min(height as f64 / view_height);
+        let offset = (
+            (width as f64 - view_width * scale) / 2.0,
+            (height as f64 - view_height * scale) / 2.0,
+        );
+
+        Ok(outlines
+            .into_iter()
+            .map(|outline| {
+                outline
+                    .into_iter()
+                    .map(|(x, y)| {
+                        (
+                            (x - min_x) * scale + offset.0,
+                            (y - min_y) * scale + offset.1,
+                        )
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+type MaskImageResponse = StdResult<EncodedImage, ApiError>;
+
+#[utoipa::path(
+    get,
+    path = "/mask",
+    params(ImageUrlQueryParams, MaskQueryParams, OutputQueryParams),
+    responses(
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
+    )
+)]
+pub async fn mask_image(
+    State(state): State<AppState>,
+    Query(source): Query<ImageUrlQueryParams>,
+    Query(mask_params): Query<MaskQueryParams>,
+    output: ImageOutput,
+) -> MaskImageResponse {
+    let bytes = fetch_raw_image(&state, &source.url).await?;
+
+    state
+        .workers
+        .run(move || mask_image_bytes(bytes, mask_params, output))
+        .await?
+}
+
+#[utoipa::path(
+    post,
+    path = "/mask",
+    params(MaskQueryParams, OutputQueryParams),
+    request_body(
+        content = inline(ImageUploadForm),
+        content_type = ["multipart/form-data", "image/*"],
+        description = "The image that should be masked (max. 3mb by default)"
+    ),
+    responses(
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
+    )
+)]
+pub async fn mask_image_upload(
+    State(state): State<AppState>,
+    Query(mask_params): Query<MaskQueryParams>,
+    output: ImageOutput,
+    ImageUpload(bytes): ImageUpload,
+) -> MaskImageResponse {
+    state
+        .workers
+        .run(move || mask_image_bytes(bytes, mask_params, output))
+        .await?
+}
+
+fn mask_image_bytes(
+    bytes: Bytes,
+    mask_params: MaskQueryParams,
+    output: ImageOutput,
+) -> MaskImageResponse {
+    let animation = animation_from_bytes(bytes)?;
+
+    let (width, height) = animation.dimensions();
+    let outlines = mask_params.outlines(width, height)?;
+    let coverage = raster::coverage(width, height, &outlines, mask_params.fill_rule);
+    let animation = animation.try_map(|mut img| {
+        mask(&mut img, &coverage);
+        Ok(img)
+    })?;
+
+    output.encode_animation(animation)
+}
+
+/// Makes everything outside of the shape transparent, `coverage` is how much of every pixel the shape covers.
+fn mask(img: &mut RgbaImage, coverage: &[f32]) {
+    for (pixel, coverage) in img.pixels_mut().zip(coverage) {
+        let alpha = &mut pixel.0[3];
+        *alpha = (*alpha as f32 * coverage.clamp(0.0, 1.0)).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn params(shape: MaskShape) -> MaskQueryParams {
+        MaskQueryParams {
+            shape,
+            exponent: defaults::exponent(),
+            sides: defaults::sides(),
+            points: defaults::points(),
+            inner_radius: defaults::inner_radius(),
+            rotation: 0.0,
+            path: None,
+            viewbox: None,
+            fill_rule: FillRule::default(),
+        }
+    }
+
+    fn path(data: &str) -> MaskQueryParams {
+        MaskQueryParams {
+            path: Some(data.to_owned()),
+            ..params(MaskShape::Path)
+        }
+    }
+
+    fn masked(params: &MaskQueryParams, (width, height): (u32, u32)) -> RgbaImage {
+        let outlines = params.outlines(width, height).unwrap();
+        let coverage = raster::coverage(width, height, &outlines, params.fill_rule);
+        let mut img = RgbaImage::from_pixel(width, height, Rgba([255, 0, 0, 255]));
+        mask(&mut img, &coverage);
+        img
+    }
+
+    fn is_bad_request(result: Result<Vec<Vec<Point>>, ApiError>) -> bool {
+        matches!(
+            result,
+            Err(ApiError::Any(StatusCode::BAD_REQUEST, _)
+                | ApiError::AnyStatic(StatusCode::BAD_REQUEST, _))
+        )
+    }
+
+    #[test]
+    fn every_shape_cuts_the_corners_and_keeps_the_center() {
+        let shapes = [
+            params(MaskShape::Squircle),
+            params(MaskShape::Circle),
+            params(MaskShape::Hexagon),
+            params(MaskShape::Star),
+            params(MaskShape::Heart),
+            params(MaskShape::Polygon),
+            path("M 32 0 L 64 24 L 32 48 L 0 24 Z"),
+        ];
+
+        for params in shapes {
+            let img = masked(&params, (64, 48));
+            for (x, y) in [(0, 0), (63, 0), (0, 47), (63, 47)] {
+                assert_eq!(img[(x, y)].0[3], 0, "{:?} at ({x}, {y})", params.shape);
+            }
+            assert_eq!(img[(32, 24)].0[3], 255, "{:?}", params.shape);
+        }
+    }
+
+    #[test]
+    fn fits_the_viewbox_into_the_image() {
+        let params = MaskQueryParams {
+            viewbox: Some("0 0 1 1".to_owned()),
+            ..path("M0 0 H1 V1 H0 Z")
+        };
+        let img = masked(&params, (64, 32));
+
+        assert_eq!(img[(15, 16)].0[3], 0);
+        assert_eq!(img[(16, 16)].0[3], 255);
+        assert_eq!(img[(47, 16)].0[3], 255);
+        assert_eq!(img[(48, 16)].0[3], 0);
+    }
+
+    #[test]
+    fn evenodd_paths_leave_holes() {
+        let params = MaskQueryParams {
+            fill_rule: FillRule::Evenodd,
+            ..path("M0 0 H32 V32 H0 Z M8 8 H24 V24 H8 Z")
+        };
+        let img = masked(&params, (32, 32));
+
+        assert_eq!(img[(16, 16)].0[3], 0);
+        assert_eq!(img[(4, 16)].0[3], 255);
+    }
+
+    #[test]
+    fn rejects_malformed_and_oversized_paths() {
+        assert!(is_bad_request(params(MaskShape::Path).outlines(8, 8)));
+        assert!(is_bad_request(path("M 0 0 L").outlines(8, 8)));
+        assert!(is_bad_request(path("Q 1 1 2 2").outlines(8, 8)));
+
+        let oversized = format!("M0 0{}", " L1 1".repeat(MAX_PATH_LENGTH / 5 + 1));
+        assert!(is_bad_request(path(&oversized).outlines(8, 8)));
+
+        let viewbox = MaskQueryParams {
+            viewbox: Some("0 0 0 1".to_owned()),
+            ..path("M0 0 H1 V1 Z")
+        };
+        assert!(is_bad_request(viewbox.outlines(8, 8)));
+    }
+
+    #[test]
+    fn huge_coordinates_stay_numbers() {
+        let params = MaskQueryParams {
+            viewbox: Some("0 0 1e-300 1e-300".to_owned()),
+            ..path("M-1e300 0 L1e300 1 L0 1e300 Z")
+        };
+        let outlines = params.outlines(16, 16).unwrap();
+        let coverage = raster::coverage(16, 16, &outlines, params.fill_rule);
+
+        assert!(coverage.iter().all(|coverage| coverage.is_finite()));
+    }
+}
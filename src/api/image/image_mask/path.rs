@@ -0,0 +1,441 @@
+//! Parses SVG path data (the `d` attribute) into outlines made of straight lines.
+
+use super::raster::Point;
+use std::f64::consts::TAU;
+
+/// The most points a path may be flattened into.
+const MAX_POINTS: usize = 200_000;
+
+/// Parses `data` into closed outlines, curves and arcs are approximated by straight lines.
+pub fn parse(data: &str) -> Result<Vec<Vec<Point>>, String> {
+    let mut parser = Parser {
+        data: data.as_bytes(),
+        position: 0,
+    };
+    let mut builder = Builder::default();
+
+    let mut command = None;
+    let mut started = false;
+    loop {
+        parser.skip_separators();
+        if parser.is_done() {
+            break;
+        }
+
+        let explicit = parser.command();
+        command = match (explicit, command) {
+            (Some(next), _) => Some(next),
+            (None, None) => return Err("it has to start with `M`".to_owned()),
+            // `M` followed by more coordinates means `L`.
+            (None, Some(b'M')) => Some(b'L'),
+            (None, Some(b'm')) => Some(b'l'),
+            (None, Some(b'Z' | b'z')) => {
+                return Err(format!("unexpected number at {}", parser.position))
+            }
+            (None, previous) => previous,
+        };
+
+        let command = command.unwrap();
+        if !started && !matches!(command, b'M' | b'm') {
+            return Err("it has to start with `M`".to_owned());
+        }
+        started = true;
+        let relative = command.is_ascii_lowercase();
+        let origin = if relative {
+            builder.current
+        } else {
+            (0.0, 0.0)
+        };
+        let offset = |(x, y): Point| (origin.0 + x, origin.1 + y);
+
+        match command.to_ascii_uppercase() {
+            b'M' => builder.move_to(offset(parser.point()?)),
+            b'L' => builder.line_to(offset(parser.point()?)),
+            b'H' => {
+                let x = parser.number()?;
+                let x = if relative { origin.0 + x } else { x };
+                builder.line_to((x, builder.current.1));
+            }
+            b'V' => {
+                let y = parser.number()?;
+                let y = if relative { origin.1 + y } else { y };
+                builder.line_to((builder.current.0, y));
+            }
+            b'C' => {
+                let (c1, c2, end) = (parser.point()?, parser.point()?, parser.point()?);
+                builder.cubic_to(offset(c1), offset(c2), offset(end));
+            }
+            b'S' => {
+                let (c2, end) = (parser.point()?, parser.point()?);
+                let c1 = builder.reflected_control(b'C');
+                builder.cubic_to(c1, offset(c2), offset(end));
+            }
+            b'Q' => {
+                let (control, end) = (parser.point()?, parser.point()?);
+                builder.quadratic_to(offset(control), offset(end));
+            }
+            b'T' => {
+                let end = parser.point()?;
+                let control = builder.reflected_control(b'Q');
+                builder.quadratic_to(control, offset(end));
+            }
+            b'A' => {
+                let (rx, ry, rotation) = (parser.number()?, parser.number()?, parser.number()?);
+                let (large_arc, sweep) = (parser.flag()?, parser.flag()?);
+                let end = offset(parser.point()?);
+                builder.arc_to(rx, ry, rotation, large_arc, sweep, end);
+            }
+            b'Z' => builder.close(),
+            other => return Err(format!("`{}` is not a path command", char::from(other))),
+        }
+
+        if builder.points > MAX_POINTS {
+            return Err(format!("it has more than {MAX_POINTS} points"));
+        }
+    }
+
+    builder.close();
+    Ok(builder.outlines)
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn is_done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.position).copied()
+    }
+
+    fn skip_separators(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r' | b',')) {
+            self.position += 1;
+        }
+    }
+
+    fn command(&mut self) -> Option<u8> {
+        let command = self.peek().filter(|char| char.is_ascii_alphabetic())?;
+        self.position += 1;
+        Some(command)
+    }
+
+    /// A number like `-1.5`, `.5` or `1e3`, which doesn't need a separator if it starts with a sign or a second dot.
+    fn number(&mut self) -> Result<f64, String> {
+        self.skip_separators();
+        let start = self.position;
+
+        if matches!(self.peek(), Some(b'+' | b'-')) {
+            self.position += 1;
+        }
+        let mut seen_dot = false;
+        let mut digits = 0;
+        while let Some(char) = self.peek() {
+            match char {
+                b'0'..=b'9' => digits += 1,
+                b'.' if !seen_dot => seen_dot = true,
+                _ => break,
+            }
+            self.position += 1;
+        }
+        if digits > 0 && matches!(self.peek(), Some(b'e' | b'E')) {
+            let exponent_start = self.position;
+            self.position += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.position += 1;
+            }
+            if !matches!(self.peek(), Some(b'0'..=b'9')) {
+                self.position = exponent_start;
+            }
+            while matches!(self.peek(), Some(b'0'..=b'9')) {
+                self.position += 1;
+            }
+        }
+
+        std::str::from_utf8(&self.data[start..self.position])
+            .ok()
+            .filter(|_| digits > 0)
+            .and_then(|number| number.parse::<f64>().ok())
+            .filter(|number| number.is_finite())
+            .ok_or_else(|| format!("expected a number at {start}"))
+    }
+
+    /// An arc flag, which is a single `0` or `1` that may be followed by the next number right away.
+    fn flag(&mut self) -> Result<bool, String> {
+        self.skip_separators();
+        let flag = match self.peek() {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            _ => return Err(format!("expected `0` or `1` at {}", self.position)),
+        };
+        self.position += 1;
+        Ok(flag)
+    }
+
+    fn point(&mut self) -> Result<Point, String> {
+        Ok((self.number()?, self.number()?))
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    outlines: Vec<Vec<Point>>,
+    outline: Vec<Point>,
+    start: Point,
+    current: Point,
+    /// The last control point and whether it belonged to a cubic (`C`) or quadratic (`Q`) curve
+    last_control: Option<(Point, u8)>,
+    points: usize,
+}
+
+impl Builder {
+    fn move_to(&mut self, point: Point) {
+        self.close();
+        self.start = point;
+        self.current = point;
+        self.outline.push(point);
+        self.last_control = None;
+    }
+
+    fn line_to(&mut self, point: Point) {
+        if self.outline.is_empty() {
+            self.outline.push(self.current);
+        }
+        self.outline.push(point);
+        self.current = point;
+        self.last_control = None;
+        self.points += 1;
+    }
+
+    fn close(&mut self) {
+        if self.outline.len() > 2 {
+            self.outlines.push(std::mem::take(&mut self.outline));
+        }
+        self.outline.clear();
+        self.current = self.start;
+        self.last_control = None;
+    }
+
+    /// The first control point of a smooth curve, the last one mirrored at the current point.
+    fn reflected_control(&self, kind: u8) -> Point {
+        match self.last_control {
+            Some((control, last_kind)) if last_kind == kind => (
+                2.0 * self.current.0 - control.0,
+                2.0 * self.current.1 - control.1,
+            ),
+            _ => self.current,
+        }
+    }
+
+    fn segments(points: &[Point]) -> usize {
+        let length: f64 = points
+            .windows(2)
+            .map(|pair| (pair[1].0 - pair[0].0).hypot(pair[1].1 - pair[0].1))
+            .sum();
+        (length / 2.0).clamp(4.0, 256.0) as usize
+    }
+
+    fn cubic_to(&mut self, c1: Point, c2: Point, end: Point) {
+        let start = self.current;
+        let segments = Self::segments(&[start, c1, c2, end]);
+
+        for step in 1..=segments {
+            let t = step as f64 / segments as f64;
+            let u = 1.0 - t;
+            let at = |a: f64, b: f64, c: f64, d: f64| {
+                u * u * u * a + 3.0 * u * u * t * b + 3.0 * u * t * t * c + t * t * t * d
+            };
+            self.line_to((
+                at(start.0, c1.0, c2.0, end.0),
+                at(start.1, c1.1, c2.1, end.1),
+            ));
+        }
+        self.last_control = Some((c2, b'C'));
+    }
+
+    fn quadratic_to(&mut self, control: Point, end: Point) {
+        let start = self.current;
+        let segments = Self::segments(&[start, control, end]);
+
+        for step in 1..=segments {
+            let t = step as f64 / segments as f64;
+            let u = 1.0 - t;
+            let at = |a: f64, b: f64, c: f64| u * u * a + 2.0 * u * t * b + t * t * c;
+            self.line_to((at(start.0, control.0, end.0), at(start.1, control.1, end.1)));
+        }
+        self.last_control = Some((control, b'Q'));
+    }
+
+    /// An elliptical arc, converted to its center as described in the SVG specification (appendix B.2.4).
+    fn arc_to(
+        &mut self,
+        rx: f64,
+        ry: f64,
+        rotation: f64,
+        large_arc: bool,
+        sweep: bool,
+        end: Point,
+    ) {
+        let start = self.current;
+        let (mut rx, mut ry) = (rx.abs(), ry.abs());
+        if rx == 0.0 || ry == 0.0 || start == end {
+            self.line_to(end);
+            return;
+        }
+
+        let (sin, cos) = rotation.to_radians().sin_cos();
+        let (dx, dy) = ((start.0 - end.0) / 2.0, (start.1 - end.1) / 2.0);
+        let (x1, y1) = (cos * dx + sin * dy, -sin * dx + cos * dy);
+
+        let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+        if lambda > 1.0 {
+            rx *= lambda.sqrt();
+            ry *= lambda.sqrt();
+        }
+
+        let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+        let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+        let mut factor = (numerator / denominator).max(0.0).sqrt();
+        if large_arc == sweep {
+            factor = -factor;
+        }
+        let (cx1, cy1) = (factor * rx * y1 / ry, -factor * ry * x1 / rx);
+        let center = (
+            cos * cx1 - sin * cy1 + (start.0 + end.0) / 2.0,
+            sin * cx1 + cos * cy1 + (start.1 + end.1) / 2.0,
+        );
+
+        let angle = |ux: f64, uy: f64| uy.atan2(ux);
+        let start_angle = angle((x1 - cx1) / rx, (y1 - cy1) / ry);
+        let mut sweep_angle = angle((-x1 - cx1) / rx, (-y1 - cy1) / ry) - start_angle;
+        if sweep && sweep_angle < 0.0 {
+            sweep_angle += TAU;
+        } else if !sweep && sweep_angle > 0.0 {
+            sweep_angle -= TAU;
+        }
+
+        let segments = (sweep_angle.abs() * rx.max(ry) / 2.0).clamp(4.0, 256.0) as usize;
+        for step in 1..=segments {
+            let theta = start_angle + sweep_angle * step as f64 / segments as f64;
+            let (x, y) = (rx * theta.cos(), ry * theta.sin());
+            self.line_to((center.0 + cos * x - sin * y, center.1 + sin * x + cos * y));
+        }
+        // Lands exactly on the end, which later relative commands are based on.
+        self.current = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Point, expected: Point) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-9 && (actual.1 - expected.1).abs() < 1e-9,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn continues_move_to_with_line_to() {
+        assert_eq!(
+            parse("M0 0 10 0 10 10z").unwrap(),
+            [vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)]]
+        );
+        assert_eq!(
+            parse("m1 1 2 0 0 2z").unwrap(),
+            [vec![(1.0, 1.0), (3.0, 1.0), (3.0, 3.0)]]
+        );
+    }
+
+    #[test]
+    fn repeats_the_previous_command() {
+        assert_eq!(
+            parse("M0 0 L1 0 1 1 0 1 Z").unwrap(),
+            [vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]]
+        );
+        assert_eq!(
+            parse("M0 0 h2 v2 h-2 v-2").unwrap(),
+            [vec![
+                (0.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 2.0),
+                (0.0, 2.0),
+                (0.0, 0.0)
+            ]]
+        );
+    }
+
+    #[test]
+    fn parses_numbers_without_separators() {
+        assert_eq!(
+            parse("M1.5.5L-1-2 1e1,2E-1z").unwrap(),
+            [vec![(1.5, 0.5), (-1.0, -2.0), (10.0, 0.2)]]
+        );
+    }
+
+    #[test]
+    fn starts_a_new_outline_on_move_to() {
+        let outlines = parse("M0 0 L4 0 L4 4 Z M1 1 L3 1 L3 3 Z").unwrap();
+        assert_eq!(outlines.len(), 2);
+        assert_eq!(outlines[1][0], (1.0, 1.0));
+    }
+
+    #[test]
+    fn converts_arcs_to_their_center() {
+        // Flags don't need separators, `1010 0` are the flags 1 and 0 and the point (10, 0).
+        for (data, sweep_below) in [("M0 0a5 5 0 1010 0", true), ("M0 0 A5 5 0 0 1 10 0", false)] {
+            let outline = &parse(data).unwrap()[0];
+
+            assert_close(*outline.last().unwrap(), (10.0, 0.0));
+            for &(x, y) in outline {
+                assert!(
+                    ((x - 5.0).hypot(y) - 5.0).abs() < 1e-9,
+                    "{data}: ({x}, {y})"
+                );
+                assert!(
+                    y.abs() < 1e-9 || (y > 0.0) == sweep_below,
+                    "{data}: ({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn scales_up_arcs_that_are_too_small() {
+        let outline = &parse("M0 0 A1 1 0 0 1 10 0").unwrap()[0];
+
+        assert_close(*outline.last().unwrap(), (10.0, 0.0));
+        assert!(outline
+            .iter()
+            .all(|&(x, y)| ((x - 5.0).hypot(y) - 5.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        for data in [
+            "L 1 1",
+            "M 0",
+            "M 0 0 L 1",
+            "M 0 0 X 1 1",
+            "M 0 0 L 1e999 0",
+            "M 0 0 A 1 1 0 2 0 1 1",
+            "M 0 0 L 1 1 Z 2 2",
+            "M 0 0 L . 1",
+        ] {
+            assert!(parse(data).is_err(), "{data}");
+        }
+    }
+
+    #[test]
+    fn limits_the_number_of_points() {
+        let curve = " c 0 1000 1000 1000 1000 0";
+        let data = format!("M0 0{}", curve.repeat(800));
+
+        assert!(parse(&data).unwrap_err().contains("points"));
+    }
+}
@@ -0,0 +1,218 @@
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+/// A point in pixels, `(0.0, 0.0)` is the top left corner of the image.
+pub type Point = (f64, f64);
+
+/// Every pixel row is sampled this many times, horizontally the coverage is exact.
+const SUBSAMPLES: u32 = 16;
+
+/// Which parts of self-intersecting or nested outlines are inside, see SVG's `fill-rule`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FillRule {
+    #[default]
+    Nonzero,
+    Evenodd,
+}
+
+impl FillRule {
+    fn is_inside(self, winding: i32) -> bool {
+        match self {
+            Self::Nonzero => winding != 0,
+            Self::Evenodd => winding % 2 != 0,
+        }
+    }
+}
+
+/// Coordinates are clamped to this, far outside of any image, so the math on them can't overflow.
+const MAX_COORDINATE: f64 = 1e9;
+
+struct Edge {
+    top: f64,
+    bottom: f64,
+    /// The x coordinate at `top`
+    top_x: f64,
+    /// The x coordinate at `bottom`
+    bottom_x: f64,
+    winding: i32,
+}
+
+impl Edge {
+    /// Where the edge crosses the row at `y`, interpolated so even almost horizontal edges can't overflow.
+    fn x_at(&self, y: f64) -> f64 {
+        self.top_x + (self.bottom_x - self.top_x) * ((y - self.top) / (self.bottom - self.top))
+    }
+}
+
+/// How much of every pixel lies inside the closed `outlines`, from `0.0` to `1.0`, row by row.
+///
+/// Points that aren't numbers are skipped.
+pub fn coverage(width: u32, height: u32, outlines: &[Vec<Point>], fill_rule: FillRule) -> Vec<f32> {
+    let clamp = |&(x, y): &Point| {
+        (
+            x.clamp(-MAX_COORDINATE, MAX_COORDINATE),
+            y.clamp(-MAX_COORDINATE, MAX_COORDINATE),
+        )
+    };
+    let outlines: Vec<Vec<Point>> = outlines
+        .iter()
+        .map(|outline| {
+            outline
+                .iter()
+                .filter(|(x, y)| !x.is_nan() && !y.is_nan())
+                .map(clamp)
+                .collect()
+        })
+        .collect();
+
+    let mut edges: Vec<Edge> = outlines
+        .iter()
+        .flat_map(|outline| {
+            outline
+                .iter()
+                .zip(outline.iter().cycle().skip(1))
+                .filter(|(start, end)| start.1 != end.1)
+                .map(|(&(x0, y0), &(x1, y1))| {
+                    let ((top_x, top), (bottom_x, bottom), winding) = if y0 < y1 {
+                        ((x0, y0), (x1, y1), 1)
+                    } else {
+                        ((x1, y1), (x0, y0), -1)
+                    };
+
+                    Edge {
+                        top,
+                        bottom,
+                        top_x,
+                        bottom_x,
+                        winding,
+                    }
+                })
+        })
+        .collect();
+    edges.sort_by(|a, b| a.top.total_cmp(&b.top));
+
+    let mut coverage = vec![0.0f32; (width * height) as usize];
+    let mut crossings: Vec<(f64, i32)> = Vec::new();
+    let mut active: Vec<&Edge> = Vec::new();
+    let mut next_edge = 0;
+    let weight = 1.0 / SUBSAMPLES as f64;
+
+    for row in 0..height {
+        let row_coverage = &mut coverage[(row * width) as usize..((row + 1) * width) as usize];
+        let (row_top, row_bottom) = (row as f64, row as f64 + 1.0);
+
+        while next_edge < edges.len() && edges[next_edge].top < row_bottom {
+            active.push(&edges[next_edge]);
+            next_edge += 1;
+        }
+        active.retain(|edge| edge.bottom > row_top);
+
+        for subsample in 0..SUBSAMPLES {
+            let y = row_top + (subsample as f64 + 0.5) * weight;
+
+            crossings.clear();
+            crossings.extend(
+                active
+                    .iter()
+                    .filter(|edge| edge.top <= y && y < edge.bottom)
+                    .map(|edge| (edge.x_at(y), edge.winding)),
+            );
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                if fill_rule.is_inside(winding) {
+                    add_span(row_coverage, pair[0].0, pair[1].0, weight);
+                }
+            }
+        }
+    }
+
+    coverage
+}
+
+/// Adds `weight` to every pixel between `start` and `end`, the pixels at both ends only get their covered share.
+fn add_span(row: &mut [f32], start: f64, end: f64, weight: f64) {
+    let width = row.len() as f64;
+    let (start, end) = (start.clamp(0.0, width), end.clamp(0.0, width));
+    if end <= start {
+        return;
+    }
+
+    let (first, last) = (start.floor() as usize, end.floor() as usize);
+    if first == last {
+        row[first] += ((end - start) * weight) as f32;
+        return;
+    }
+
+    row[first] += ((first as f64 + 1.0 - start) * weight) as f32;
+    for pixel in &mut row[first + 1..last] {
+        *pixel += weight as f32;
+    }
+    if last < row.len() {
+        row[last] += ((end - last as f64) * weight) as f32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f64, max: f64) -> Vec<Point> {
+        vec![(min, min), (max, min), (max, max), (min, max)]
+    }
+
+    fn at(coverage: &[f32], width: u32, (x, y): (u32, u32)) -> f32 {
+        coverage[(y * width + x) as usize]
+    }
+
+    #[test]
+    fn covers_partial_pixels_by_their_share() {
+        let coverage = coverage(8, 8, &[square(2.5, 6.0)], FillRule::Nonzero);
+
+        assert_eq!(at(&coverage, 8, (4, 4)), 1.0);
+        assert_eq!(at(&coverage, 8, (1, 4)), 0.0);
+        assert_eq!(at(&coverage, 8, (6, 4)), 0.0);
+        assert!((at(&coverage, 8, (2, 4)) - 0.5).abs() < 1e-6);
+        assert!((at(&coverage, 8, (2, 2)) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn evenodd_leaves_a_hole_in_nested_outlines() {
+        let outlines = [square(0.0, 10.0), square(3.0, 7.0)];
+
+        let evenodd = coverage(10, 10, &outlines, FillRule::Evenodd);
+        assert_eq!(at(&evenodd, 10, (5, 5)), 0.0);
+        assert_eq!(at(&evenodd, 10, (1, 5)), 1.0);
+
+        // Both squares wind the same way, so nonzero fills the hole.
+        let nonzero = coverage(10, 10, &outlines, FillRule::Nonzero);
+        assert_eq!(at(&nonzero, 10, (5, 5)), 1.0);
+
+        let mut reversed = square(3.0, 7.0);
+        reversed.reverse();
+        let nonzero = coverage(10, 10, &[square(0.0, 10.0), reversed], FillRule::Nonzero);
+        assert_eq!(at(&nonzero, 10, (5, 5)), 0.0);
+    }
+
+    #[test]
+    fn survives_huge_coordinates() {
+        let outlines = [
+            vec![(-1e308, 0.0), (1e308, 1e-300), (0.0, 1e308)],
+            vec![
+                (0.0, 0.0),
+                (f64::INFINITY, 5.0),
+                (f64::NAN, 8.0),
+                (0.0, 8.0),
+            ],
+            vec![(1.0, 1.0), (1e300, 1.0 + 1e-12), (2.0, 3.0)],
+        ];
+
+        for fill_rule in [FillRule::Nonzero, FillRule::Evenodd] {
+            let coverage = coverage(16, 16, &outlines, fill_rule);
+            assert!(coverage.iter().all(|coverage| coverage.is_finite()));
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use super::raster::Point;
+use std::f64::consts::{PI, TAU};
+
+/// How many points curved outlines are made of, depending on their size.
+fn curve_points(width: f64, height: f64) -> usize {
+    ((width + height) * 2.0).clamp(128.0, 8192.0) as usize
+}
+
+/// A superellipse `|x|^n + |y|^n = 1` stretched over the whole image, `2` is an ellipse and bigger exponents get more square.
+pub fn superellipse(width: u32, height: u32, exponent: f64) -> Vec<Point> {
+    let (a, b) = (width as f64 / 2.0, height as f64 / 2.0);
+    let points = curve_points(a, b);
+
+    (0..points)
+        .map(|index| {
+            let t = TAU * index as f64 / points as f64;
+            let (sin, cos) = t.sin_cos();
+            (
+                a + a * cos.signum() * cos.abs().powf(2.0 / exponent),
+                b + b * sin.signum() * sin.abs().powf(2.0 / exponent),
+            )
+        })
+        .collect()
+}
+
+pub fn circle(width: u32, height: u32) -> Vec<Point> {
+    let diameter = width.min(height) as f64;
+    let points = curve_points(diameter, diameter);
+
+    let circle = (0..points)
+        .map(|index| (TAU * index as f64 / points as f64).sin_cos())
+        .map(|(sin, cos)| (cos, sin))
+        .collect();
+    fit(circle, width, height)
+}
+
+/// A regular polygon with a corner at the top, turned clockwise by `rotation` degrees.
+pub fn polygon(width: u32, height: u32, sides: u32, rotation: f64) -> Vec<Point> {
+    let corners = (0..sides)
+        .map(|index| corner(index as f64 / sides as f64, 1.0, rotation))
+        .collect();
+    fit(corners, width, height)
+}
+
+/// A star with `points` tips, `inner_radius` is the distance of the inner corners to the center relative to the tips.
+pub fn star(width: u32, height: u32, points: u32, inner_radius: f64, rotation: f64) -> Vec<Point> {
+    let corners = (0..points * 2)
+        .map(|index| {
+            let radius = if index % 2 == 0 { 1.0 } else { inner_radius };
+            corner(index as f64 / (points * 2) as f64, radius, rotation)
+        })
+        .collect();
+    fit(corners, width, height)
+}
+
+pub fn heart(width: u32, height: u32) -> Vec<Point> {
+    let points = curve_points(width as f64, height as f64);
+
+    let heart = (0..points)
+        .map(|index| {
+            let t = TAU * index as f64 / points as f64;
+            let x = 16.0 * t.sin().powi(3);
+            let y =
+                13.0 * t.cos() - 5.0 * (2.0 * t).cos() - 2.0 * (3.0 * t).cos() - (4.0 * t).cos();
+            // The formula has y pointing upwards.
+            (x, -y)
+        })
+        .collect();
+    fit(heart, width, height)
+}
+
+/// A point `turn` of the way around a circle with the given radius, starting at the top.
+fn corner(turn: f64, radius: f64, rotation: f64) -> Point {
+    let angle = turn * TAU + rotation.to_radians() - PI / 2.0;
+    let (sin, cos) = angle.sin_cos();
+    (radius * cos, radius * sin)
+}
+
+/// Scales an outline evenly until it touches the edges of the image, and centers it.
+pub fn fit(outline: Vec<Point>, width: u32, height: u32) -> Vec<Point> {
+    let (min, max) = bounds(&outline);
+    let (outline_width, outline_height) = (max.0 - min.0, max.1 - min.1);
+    if outline_width <= 0.0 || outline_height <= 0.0 {
+        return Vec::new();
+    }
+
+    let (width, height) = (width as f64, height as f64);
+    let scale = (width / outline_width).min(height / outline_height);
+    let offset = (
+        (width - outline_width * scale) / 2.0,
+        (height - outline_height * scale) / 2.0,
+    );
+
+    outline
+        .into_iter()
+        .map(|(x, y)| {
+            (
+                (x - min.0) * scale + offset.0,
+                (y - min.1) * scale + offset.1,
+            )
+        })
+        .collect()
+}
+
+/// The top left and bottom right corner of the box around the points.
+pub fn bounds(points: &[Point]) -> (Point, Point) {
+    points.iter().fold(
+        (
+            (f64::INFINITY, f64::INFINITY),
+            (f64::NEG_INFINITY, f64::NEG_INFINITY),
+        ),
+        |(min, max), &(x, y)| ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y))),
+    )
+}
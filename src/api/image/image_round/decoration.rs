@@ -0,0 +1,485 @@
+use super::{
+    super::{
+        gradient::logic::{parse_stops, render, GradientKind, Interpolation},
+        hex_color::HexColor,
+    },
+    logic::{cut_corners, Corners, Radii},
+};
+use crate::{error::ApiError, utils::DecodeLimits};
+use axum::http::StatusCode;
+use image::{GrayImage, Luma, Rgba, RgbaImage};
+use imageproc::filter::gaussian_blur_f32;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+const MAX_BORDER_WIDTH: u32 = 512;
+const MAX_PADDING: u32 = 1024;
+const MAX_SHADOW_BLUR: u32 = 128;
+const MAX_SHADOW_OFFSET: i32 = 1024;
+
+mod defaults {
+    use super::HexColor;
+
+    #[inline(always)]
+    pub fn border_color() -> HexColor {
+        HexColor::opaque([0, 0, 0])
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DecorationQueryParams {
+    /// The width of a border around the image in pixels, it follows the rounded corners and makes the image larger
+    #[serde(default)]
+    #[param(maximum = 512, default = 0)]
+    border_width: u32,
+
+    /// The color of the border
+    #[serde(default = "defaults::border_color")]
+    #[param(value_type = String, default = "#000000")]
+    border_color: HexColor,
+
+    /// Colors the border with a gradient instead, from color stops like `/gradient` takes them, e.g. `#f09433, #dc2743, #bc1888`
+    border_gradient: Option<String>,
+
+    #[serde(default)]
+    #[param(inline)]
+    border_gradient_kind: GradientKind,
+
+    /// The direction of a linear border gradient (0 points up, defaults to 180) or the start of a conic one (defaults to 0), in degrees
+    border_gradient_angle: Option<f32>,
+
+    /// Adds a drop shadow in this color behind the image, e.g. `#00000080`
+    #[param(value_type = Option<String>)]
+    shadow_color: Option<HexColor>,
+
+    /// How far the shadow is blurred, in pixels
+    #[serde(default)]
+    #[param(maximum = 128, default = 0)]
+    shadow_blur: u32,
+
+    /// How far the shadow is moved to the right, in pixels
+    #[serde(default)]
+    #[param(minimum = -1024, maximum = 1024, default = 0)]
+    shadow_offset_x: i32,
+
+    /// How far the shadow is moved down, in pixels
+    #[serde(default)]
+    #[param(minimum = -1024, maximum = 1024, default = 0)]
+    shadow_offset_y: i32,
+
+    /// Empty space around the image (and its border and shadow), in pixels
+    #[serde(default)]
+    #[param(maximum = 1024, default = 0)]
+    padding: u32,
+
+    /// Fills the whole canvas behind the image, including the rounded corners and `padding`, defaults to transparent
+    #[param(value_type = Option<String>)]
+    background: Option<HexColor>,
+}
+
+impl DecorationQueryParams {
+    fn is_empty(&self) -> bool {
+        self.border_width == 0
+            && self.shadow_color.is_none()
+            && self.padding == 0
+            && self.background.is_none()
+    }
+
+    fn validate(&self) -> Result<(), ApiError> {
+        let bad_request =
+            |message: &'static str| ApiError::AnyStatic(StatusCode::BAD_REQUEST, message);
+
+        if self.border_width > MAX_BORDER_WIDTH {
+            return Err(bad_request("`border_width` cannot exceed 512."));
+        }
+        if self.padding > MAX_PADDING {
+            return Err(bad_request("`padding` cannot exceed 1024."));
+        }
+        if self.shadow_blur > MAX_SHADOW_BLUR {
+            return Err(bad_request("`shadow_blur` cannot exceed 128."));
+        }
+        if [self.shadow_offset_x, self.shadow_offset_y]
+            .iter()
+            .any(|offset| offset.abs() > MAX_SHADOW_OFFSET)
+        {
+            return Err(bad_request(
+                "`shadow_offset_x` and `shadow_offset_y` must be between -1024 and 1024.",
+            ));
+        }
+        if self
+            .border_gradient_angle
+            .is_some_and(|angle| !angle.is_finite())
+        {
+            return Err(bad_request(
+                "`border_gradient_angle` must be a finite number.",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Validates the parameters and lays out the decoration of images with the given size, whose corners were cut
+    /// with `radii`. Everything that is the same for every frame of an animation is only done once.
+    pub fn prepare(
+        &self,
+        (width, height): (u32, u32),
+        radii: Corners,
+    ) -> Result<Decoration<'_>, ApiError> {
+        self.validate()?;
+
+        let border = self.border_width;
+        let (shape_width, shape_height) = (
+            width as i64 + 2 * border as i64,
+            height as i64 + 2 * border as i64,
+        );
+
+        // The shadow can stick out on any side, the canvas grows to fit both it and the image.
+        let (mut left, mut top, mut right, mut bottom) = (0, 0, shape_width, shape_height);
+        if self.shadow_color.is_some() {
+            let spread = self.shadow_blur as i64;
+            let (offset_x, offset_y) = (self.shadow_offset_x as i64, self.shadow_offset_y as i64);
+            left = left.min(offset_x - spread);
+            top = top.min(offset_y - spread);
+            right = right.max(shape_width + offset_x + spread);
+            bottom = bottom.max(shape_height + offset_y + spread);
+        }
+        let padding = self.padding as i64;
+        let (canvas_width, canvas_height) =
+            (right - left + 2 * padding, bottom - top + 2 * padding);
+        let canvas = (
+            u32::try_from(canvas_width).unwrap_or(u32::MAX),
+            u32::try_from(canvas_height).unwrap_or(u32::MAX),
+        );
+        DecodeLimits::from_config().check(canvas.0, canvas.1)?;
+
+        let ring = if border > 0 {
+            Some(self.ring((width, height), radii)?)
+        } else {
+            None
+        };
+
+        Ok(Decoration {
+            params: self,
+            canvas,
+            origin: (padding - left, padding - top),
+            ring,
+        })
+    }
+
+    /// The border that follows the corners of the image, like CSS draws the outer edge of a rounded border, with a
+    /// hole for the image.
+    fn ring(
+        &self,
+        (width, height): (u32, u32),
+        (tl, tr, bl, br): Corners,
+    ) -> Result<RgbaImage, ApiError> {
+        let border = self.border_width;
+        let (shape_width, shape_height) = (width + 2 * border, height + 2 * border);
+
+        let mut ring = match &self.border_gradient {
+            Some(stops) => {
+                let angle = self
+                    .border_gradient_angle
+                    .unwrap_or(match self.border_gradient_kind {
+                        GradientKind::Linear => 180.0,
+                        GradientKind::Radial | GradientKind::Conic => 0.0,
+                    });
+                render(
+                    &parse_stops(stops)?,
+                    self.border_gradient_kind,
+                    angle,
+                    Interpolation::default(),
+                    (shape_width, shape_height),
+                )
+            }
+            None => {
+                RgbaImage::from_pixel(shape_width, shape_height, Rgba(self.border_color.rgba()))
+            }
+        };
+
+        // Square corners stay square, the others grow by the width of the border.
+        let outer = |(rx, ry): Radii| {
+            if rx == 0 || ry == 0 {
+                (0, 0)
+            } else {
+                (rx + border, ry + border)
+            }
+        };
+        cut_corners(&mut ring, (outer(tl), outer(tr), outer(bl), outer(br)));
+
+        // Only the ring around the outline of the image is colored, not what's behind transparent parts of it.
+        let mut inside = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+        cut_corners(&mut inside, (tl, tr, bl, br));
+        for (x, y, pixel) in inside.enumerate_pixels() {
+            let alpha = &mut ring.get_pixel_mut(x + border, y + border).0[3];
+            *alpha = ((*alpha as u16 * (255 - pixel.0[3]) as u16 + 127) / 255) as u8;
+        }
+
+        Ok(ring)
+    }
+}
+
+/// The decoration of images of one size, see [`DecorationQueryParams::prepare`].
+pub struct Decoration<'a> {
+    params: &'a DecorationQueryParams,
+    canvas: (u32, u32),
+    /// Where the top left corner of the border, or the image if there is none, is put on the canvas
+    origin: (i64, i64),
+    ring: Option<RgbaImage>,
+}
+
+impl Decoration<'_> {
+    /// The size of the decorated image.
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.canvas
+    }
+
+    /// Adds the border, shadow, padding and background around `img`.
+    pub fn apply(&self, img: RgbaImage) -> RgbaImage {
+        let params = self.params;
+        if params.is_empty() {
+            return img;
+        }
+
+        let shape = match &self.ring {
+            Some(ring) => {
+                let mut shape = ring.clone();
+                let border = params.border_width as i64;
+                composite(&mut shape, &img, (border, border));
+                shape
+            }
+            None => img,
+        };
+
+        let (canvas_width, canvas_height) = self.canvas;
+        let mut canvas = RgbaImage::from_pixel(
+            canvas_width,
+            canvas_height,
+            Rgba(params.background.map_or([0; 4], HexColor::rgba)),
+        );
+
+        if let Some(color) = params.shadow_color {
+            let mut silhouette = GrayImage::new(canvas_width, canvas_height);
+            let (shadow_x, shadow_y) = (
+                self.origin.0 + params.shadow_offset_x as i64,
+                self.origin.1 + params.shadow_offset_y as i64,
+            );
+            for (x, y, pixel) in shape.enumerate_pixels() {
+                silhouette.put_pixel(
+                    (shadow_x + x as i64) as u32,
+                    (shadow_y + y as i64) as u32,
+                    Luma([pixel.0[3]]),
+                );
+            }
+            if params.shadow_blur > 0 {
+                silhouette = gaussian_blur_f32(&silhouette, params.shadow_blur as f32 / 2.0);
+            }
+
+            for (pixel, coverage) in canvas.pixels_mut().zip(silhouette.pixels()) {
+                let alpha = (color.alpha as u16 * coverage.0[0] as u16 + 127) / 255;
+                blend(
+                    pixel,
+                    HexColor {
+                        alpha: alpha as u8,
+                        ..color
+                    },
+                );
+            }
+        }
+
+        composite(&mut canvas, &shape, self.origin);
+        canvas
+    }
+}
+
+/// Composites `color` over `pixel` with [`HexColor::over`], which rounds unlike `Pixel::blend`. That one truncates the
+/// alpha, so anti-aliased edges over an opaque background would stay slightly transparent.
+fn blend(pixel: &mut Rgba<u8>, color: HexColor) {
+    let [red, green, blue, alpha] = pixel.0;
+    *pixel = Rgba(
+        color
+            .over(HexColor {
+                red,
+                green,
+                blue,
+                alpha,
+            })
+            .rgba(),
+    );
+}
+
+/// Composites `top` over `canvas` with its top left corner at `(x, y)`, which must be inside of `canvas`.
+fn composite(canvas: &mut RgbaImage, top: &RgbaImage, (x, y): (i64, i64)) {
+    for (top_x, top_y, pixel) in top.enumerate_pixels() {
+        let [red, green, blue, alpha] = pixel.0;
+        blend(
+            canvas.get_pixel_mut((x + top_x as i64) as u32, (y + top_y as i64) as u32),
+            HexColor {
+                red,
+                green,
+                blue,
+                alpha,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const ROUND: Radii = (10, 10);
+
+    fn params() -> DecorationQueryParams {
+        DecorationQueryParams {
+            border_width: 0,
+            border_color: defaults::border_color(),
+            border_gradient: None,
+            border_gradient_kind: GradientKind::default(),
+            border_gradient_angle: None,
+            shadow_color: None,
+            shadow_blur: 0,
+            shadow_offset_x: 0,
+            shadow_offset_y: 0,
+            padding: 0,
+            background: None,
+        }
+    }
+
+    /// A 40x30 image with its corners cut like `/image/round` does.
+    fn decorate(params: &DecorationQueryParams, radii: Radii) -> RgbaImage {
+        let corners = (radii, radii, radii, radii);
+        let mut img = RgbaImage::from_pixel(40, 30, RED);
+        cut_corners(&mut img, corners);
+
+        params
+            .prepare(img.dimensions(), corners)
+            .unwrap()
+            .apply(img)
+    }
+
+    #[test]
+    fn grows_the_canvas_for_every_option() {
+        let shadow = DecorationQueryParams {
+            shadow_color: Some(HexColor::opaque([0, 0, 0])),
+            shadow_blur: 3,
+            shadow_offset_x: 2,
+            shadow_offset_y: -4,
+            ..params()
+        };
+
+        let cases = [
+            (params(), (40, 30)),
+            (
+                DecorationQueryParams {
+                    background: Some(HexColor::opaque([255; 3])),
+                    ..params()
+                },
+                (40, 30),
+            ),
+            (
+                DecorationQueryParams {
+                    border_width: 4,
+                    ..params()
+                },
+                (48, 38),
+            ),
+            (
+                DecorationQueryParams {
+                    padding: 5,
+                    ..params()
+                },
+                (50, 40),
+            ),
+            // 1 pixel to the left and 5 to the right, 7 above and none below.
+            (shadow, (46, 37)),
+            (
+                DecorationQueryParams {
+                    border_width: 4,
+                    padding: 5,
+                    shadow_color: Some(HexColor::opaque([0, 0, 0])),
+                    shadow_offset_x: 10,
+                    shadow_offset_y: 10,
+                    ..params()
+                },
+                (68, 58),
+            ),
+        ];
+
+        for (params, dimensions) in cases {
+            assert_eq!(
+                decorate(&params, ROUND).dimensions(),
+                dimensions,
+                "{params:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn opaque_backgrounds_stay_opaque() {
+        let params = DecorationQueryParams {
+            border_width: 3,
+            border_gradient: Some("#ff000080, #0000ff".to_owned()),
+            shadow_color: Some("#00000080".parse().unwrap()),
+            shadow_blur: 6,
+            shadow_offset_x: 4,
+            shadow_offset_y: 4,
+            padding: 2,
+            background: Some(HexColor::opaque([255; 3])),
+            ..params()
+        };
+
+        let img = decorate(&params, ROUND);
+        assert!(img.pixels().all(|pixel| pixel.0[3] == 255));
+    }
+
+    #[test]
+    fn the_ring_follows_the_rounded_corners() {
+        let params = DecorationQueryParams {
+            border_width: 4,
+            ..params()
+        };
+        let img = decorate(&params, ROUND);
+        let black = Rgba([0, 0, 0, 255]);
+
+        // The outer corner is rounded with 14 pixels around (14, 14), the inner one with 10 pixels.
+        assert_eq!(img[(0, 0)].0[3], 0);
+        assert_eq!(img[(2, 2)].0[3], 0);
+        assert_eq!(img[(5, 5)], black);
+        assert_eq!(img[(8, 8)], RED);
+
+        // Along the straight sides the border is exactly 4 pixels wide.
+        assert_eq!(img[(24, 3)], black);
+        assert_eq!(img[(24, 4)], RED);
+        assert_eq!(img[(3, 19)], black);
+        assert_eq!(img[(4, 19)], RED);
+    }
+
+    #[test]
+    fn square_corners_keep_a_square_ring() {
+        let params = DecorationQueryParams {
+            border_width: 4,
+            ..params()
+        };
+        let img = decorate(&params, (0, 0));
+
+        assert_eq!(img[(0, 0)], Rgba([0, 0, 0, 255]));
+        assert_eq!(img[(4, 4)], RED);
+    }
+
+    #[test]
+    fn rejects_oversized_decorations() {
+        let corners = (ROUND, ROUND, ROUND, ROUND);
+        let params = DecorationQueryParams {
+            border_width: 513,
+            ..params()
+        };
+
+        assert!(matches!(
+            params.prepare((40, 30), corners),
+            Err(ApiError::AnyStatic(StatusCode::BAD_REQUEST, _))
+        ));
+    }
+}
@@ -1,5 +1,6 @@
-use super::RoundImageQueryParams;
+use super::{RadiusOverflow, RoundImageQueryParams};
 use crate::error::ApiError;
+use axum::http::StatusCode;
 use image::{ImageBuffer, Rgba};
 use std::cmp::min;
 
@@ -9,25 +10,98 @@ pub fn round(
 ) -> Result<(), ApiError> {
     let (width, height) = img.dimensions();
 
-    let (tl, tr, bl, br);
+    let radii = corner_radii(width, height, &params)?;
+    cut_corners(img, radii);
+    Ok(())
+}
 
-    if params.auto {
-        let smaller_dimension = min(width, height);
-        (tl, tr, bl, br) = [smaller_dimension / 2; 4].into();
-    } else {
-        (tl, tr, bl, br) = params.list_corners();
-        if tl + tr > width || tr + bl > width || tl + br > height || tr + bl > height {}
-    };
+/// A horizontal and a vertical radius in pixels.
+pub type Radii = (u32, u32);
+
+/// The radii of every corner as `(top_left, top_right, bottom_left, bottom_right)`.
+pub type Corners = (Radii, Radii, Radii, Radii);
+
+/// Makes the corners transparent outside of their radii, with anti-aliased edges.
+pub fn cut_corners(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, (tl, tr, bl, br): Corners) {
+    let (width, height) = img.dimensions();
 
     // top left
-    border_radius(img, tl, |x, y| (x - 1, y - 1));
+    cut_corner(img, tl, |x, y| (x - 1, y - 1));
     // top right
-    border_radius(img, tr, |x, y| (width - x, y - 1));
+    cut_corner(img, tr, |x, y| (width - x, y - 1));
     // bottom right
-    border_radius(img, bl, |x, y| (width - x, height - y));
+    cut_corner(img, br, |x, y| (width - x, height - y));
     // bottom left
-    border_radius(img, br, |x, y| (x - 1, height - y));
-    Ok(())
+    cut_corner(img, bl, |x, y| (x - 1, height - y));
+}
+
+fn cut_corner(
+    img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    (rx, ry): Radii,
+    coordinates: impl Fn(u32, u32) -> (u32, u32),
+) {
+    if rx == ry {
+        border_radius(img, rx, coordinates);
+    } else if rx != 0 && ry != 0 {
+        elliptical_border_radius(img, rx, ry, coordinates);
+    }
+}
+
+/// The radii of every corner as `(top_left, top_right, bottom_left, bottom_right)`, two corners on the same side never add up
+/// to more than the side is long.
+pub fn corner_radii(
+    width: u32,
+    height: u32,
+    params: &RoundImageQueryParams,
+) -> Result<Corners, ApiError> {
+    if params.auto {
+        let radius = min(width, height) / 2;
+        let radii = (radius, radius);
+        return Ok((radii, radii, radii, radii));
+    }
+
+    let (tl, tr, bl, br) = params.list_corners();
+    let [tl, tr, bl, br] = [tl, tr, bl, br].map(|radius| radius.resolve(width, height));
+    let sides = [
+        ("top", tl.0, tr.0, width, "width"),
+        ("bottom", bl.0, br.0, width, "width"),
+        ("left", tl.1, bl.1, height, "height"),
+        ("right", tr.1, br.1, height, "height"),
+    ];
+
+    // The smallest `length / sum` of all sides, every radius is scaled by it.
+    let mut scale: Option<(u128, u128)> = None;
+    for (side, first, second, length, dimension) in sides {
+        let sum = first as u128 + second as u128;
+        if sum <= length as u128 {
+            continue;
+        }
+
+        match params.overflow {
+            RadiusOverflow::Strict => {
+                return Err(ApiError::Any(
+                    StatusCode::BAD_REQUEST,
+                    format!("The {side} corners ({first} + {second}) don't fit the {dimension} of {length} pixels."),
+                ))
+            }
+            RadiusOverflow::Clamp => {
+                let length = length as u128;
+                if scale.is_none_or(|(num, den)| length * den < num * sum) {
+                    scale = Some((length, sum));
+                }
+            }
+        }
+    }
+
+    let scaled = |(rx, ry): Radii| match scale {
+        Some((num, den)) => (
+            (rx as u128 * num / den) as u32,
+            (ry as u128 * num / den) as u32,
+        ),
+        None => (rx, ry),
+    };
+
+    Ok((scaled(tl), scaled(tr), scaled(bl), scaled(br)))
 }
 
 fn border_radius(
@@ -90,7 +164,7 @@ fn border_radius(
                 p += (2 * x + 2) as i32;
             } else {
                 // draw when moving to next pixel in y-direction
-                if y % 16 == 0 {
+                if y.is_multiple_of(16) {
                     draw(img, alpha, x / 16, y / 16);
                     draw(img, alpha, y / 16, x / 16);
                     skip_draw = true;
@@ -123,3 +197,271 @@ fn border_radius(
         }
     }
 }
+
+/// Cuts off a corner along a quarter of an ellipse. Pixels on the edge are anti-aliased by how much of them lies inside, measured
+/// exactly in the vertical and in 16 steps in the horizontal direction, like [`border_radius`] does for circles.
+fn elliptical_border_radius(
+    img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
+    rx: u32,
+    ry: u32,
+    coordinates: impl Fn(u32, u32) -> (u32, u32),
+) {
+    const STEPS: u32 = 16;
+
+    let (a, b) = (rx as f64, ry as f64);
+    // How far the edge is from the corner at `x`, everything further away is inside of the image.
+    let edge = |x: f64| {
+        let dx = (a - x) / a;
+        b - b * (1.0 - dx * dx).max(0.0).sqrt()
+    };
+
+    for x in 0..rx {
+        // The edge only gets closer to the corner from left to right.
+        let (highest, lowest) = (edge(x as f64), edge(x as f64 + 1.0));
+
+        for y in 0..ry {
+            let top = y as f64;
+            if top >= highest {
+                break;
+            }
+
+            let coverage = if top + 1.0 <= lowest {
+                0.0
+            } else {
+                (0..STEPS)
+                    .map(|step| {
+                        let edge = edge(x as f64 + (step as f64 + 0.5) / STEPS as f64);
+                        (top + 1.0 - edge).clamp(0.0, 1.0)
+                    })
+                    .sum::<f64>()
+                    / STEPS as f64
+            };
+
+            let alpha = &mut img[coordinates(x + 1, y + 1)].0[3];
+            *alpha = (*alpha as f64 * coverage).round() as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::image::image_round::CornerRadius;
+
+    const OPAQUE: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    fn params(
+        corner_radius: u32,
+        corners: [Option<u32>; 4],
+        overflow: RadiusOverflow,
+    ) -> RoundImageQueryParams {
+        params_with(
+            corners.map(|radius| radius.map(CornerRadius::pixels)),
+            overflow,
+        )
+        .corner_radius(CornerRadius::pixels(corner_radius))
+    }
+
+    fn params_with(
+        [top_left, top_right, bottom_left, bottom_right]: [Option<CornerRadius>; 4],
+        overflow: RadiusOverflow,
+    ) -> RoundImageQueryParams {
+        RoundImageQueryParams {
+            auto: false,
+            corner_radius: CornerRadius::pixels(0),
+            top_left,
+            top_right,
+            bottom_left,
+            bottom_right,
+            overflow,
+        }
+    }
+
+    impl RoundImageQueryParams {
+        fn corner_radius(self, corner_radius: CornerRadius) -> Self {
+            Self {
+                corner_radius,
+                ..self
+            }
+        }
+    }
+
+    fn radius(radius: &str) -> CornerRadius {
+        radius.parse().unwrap()
+    }
+
+    /// Every combination of corners, in the order top left, top right, bottom left and bottom right.
+    fn corner_combinations<T: Copy>(radius: T) -> impl Iterator<Item = [Option<T>; 4]> {
+        (0..16u8).map(move |mask| {
+            [0, 1, 2, 3].map(|corner| (mask & (1 << corner) != 0).then_some(radius))
+        })
+    }
+
+    fn corner_alphas(img: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> [u8; 4] {
+        let (width, height) = img.dimensions();
+        [
+            img[(0, 0)].0[3],
+            img[(width - 1, 0)].0[3],
+            img[(0, height - 1)].0[3],
+            img[(width - 1, height - 1)].0[3],
+        ]
+    }
+
+    #[test]
+    fn rounds_exactly_the_requested_corners() {
+        for corners in corner_combinations(8) {
+            let mut img = ImageBuffer::from_pixel(32, 24, OPAQUE);
+            round(&mut img, params(0, corners, RadiusOverflow::Strict)).unwrap();
+
+            let expected = corners.map(|radius| if radius.is_some() { 0 } else { 255 });
+            assert_eq!(corner_alphas(&img), expected, "corners {corners:?}");
+            assert_eq!(img[(16, 12)].0[3], 255);
+        }
+    }
+
+    #[test]
+    fn clamps_oversized_radii_without_panicking() {
+        for corners in corner_combinations(u32::MAX) {
+            let mut img = ImageBuffer::from_pixel(40, 30, OPAQUE);
+            round(&mut img, params(0, corners, RadiusOverflow::Clamp)).unwrap();
+
+            // A clamped radius can reach the neighbouring corner, which is then anti-aliased but never cut off.
+            for (radius, alpha) in corners.iter().zip(corner_alphas(&img)) {
+                match radius {
+                    Some(_) => assert_eq!(alpha, 0, "corners {corners:?}"),
+                    None => assert!(alpha > 0, "corners {corners:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_scales_every_radius_by_the_same_factor() {
+        // The top corners need 120 of 100 pixels, so everything is scaled by 5/6.
+        let corners = [Some(60), Some(60), Some(30), Some(0)];
+        let radii = corner_radii(100, 200, &params(0, corners, RadiusOverflow::Clamp)).unwrap();
+        assert_eq!(radii, ((50, 50), (50, 50), (25, 25), (0, 0)));
+
+        // The left side is the tightest one.
+        let corners = [Some(40), Some(10), Some(40), Some(10)];
+        let radii = corner_radii(100, 40, &params(0, corners, RadiusOverflow::Clamp)).unwrap();
+        assert_eq!(radii, ((20, 20), (5, 5), (20, 20), (5, 5)));
+    }
+
+    #[test]
+    fn clamp_keeps_radii_that_fit() {
+        let corners = [Some(50), Some(50), Some(20), Some(30)];
+        let radii = corner_radii(100, 80, &params(0, corners, RadiusOverflow::Clamp)).unwrap();
+        assert_eq!(radii, ((50, 50), (50, 50), (20, 20), (30, 30)));
+    }
+
+    #[test]
+    fn strict_rejects_every_side_that_overflows() {
+        let cases = [
+            ([Some(30), Some(30), None, None], "top"),
+            ([None, None, Some(30), Some(30)], "bottom"),
+            ([Some(30), None, Some(30), None], "left"),
+            ([None, Some(30), None, Some(30)], "right"),
+        ];
+
+        for (corners, side) in cases {
+            let result = corner_radii(50, 50, &params(0, corners, RadiusOverflow::Strict));
+            match result {
+                Err(ApiError::Any(status, message)) => {
+                    assert_eq!(status, StatusCode::BAD_REQUEST);
+                    assert!(message.contains(side), "{message} should name the {side}");
+                }
+                other => panic!("expected an error for the {side} corners, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn strict_accepts_radii_that_exactly_fit() {
+        let corners = [Some(25), Some(25), Some(25), Some(25)];
+        let radii = corner_radii(50, 50, &params(0, corners, RadiusOverflow::Strict)).unwrap();
+        assert_eq!(radii, ((25, 25), (25, 25), (25, 25), (25, 25)));
+    }
+
+    #[test]
+    fn auto_uses_half_the_shorter_side() {
+        let mut params = params(3, [None; 4], RadiusOverflow::Strict);
+        params.auto = true;
+        let radii = (10, 10);
+        assert_eq!(
+            corner_radii(40, 20, &params).unwrap(),
+            (radii, radii, radii, radii)
+        );
+    }
+
+    #[test]
+    fn parses_corner_radii() {
+        assert_eq!(radius("20"), CornerRadius::pixels(20));
+        assert_eq!(radius("20px"), CornerRadius::pixels(20));
+        assert_eq!(radius("20%").resolve(200, 100), (20, 20));
+        assert_eq!(radius("20% / 10%").resolve(100, 300), (20, 10));
+        assert_eq!(radius("30/10px").resolve(100, 100), (30, 10));
+
+        for invalid in ["", "abc", "-5", "-5%", "NaN%", "1/2/3", "20%%"] {
+            assert!(invalid.parse::<CornerRadius>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn rounds_exactly_the_requested_elliptical_corners() {
+        for corners in corner_combinations(radius("25% / 50%")) {
+            let mut img = ImageBuffer::from_pixel(48, 32, OPAQUE);
+            round(&mut img, params_with(corners, RadiusOverflow::Strict)).unwrap();
+
+            let expected = corners.map(|radius| if radius.is_some() { 0 } else { 255 });
+            assert_eq!(corner_alphas(&img), expected, "corners {corners:?}");
+            assert_eq!(img[(24, 16)].0[3], 255);
+        }
+    }
+
+    #[test]
+    fn elliptical_corners_follow_the_ellipse() {
+        let mut img = ImageBuffer::from_pixel(100, 100, OPAQUE);
+        let corners = [Some(radius("40 / 10")), None, None, None];
+        round(&mut img, params_with(corners, RadiusOverflow::Strict)).unwrap();
+
+        // The corner is 40 pixels wide but only 10 pixels high.
+        assert_eq!(img[(0, 0)].0[3], 0);
+        assert!(img[(0, 9)].0[3] < 255);
+        assert_eq!(img[(0, 10)].0[3], 255);
+        assert_eq!(img[(10, 1)].0[3], 0);
+        assert_eq!(img[(10, 4)].0[3], 255);
+        assert_eq!(img[(39, 0)].0[3], 255);
+        assert_eq!(img[(40, 0)].0[3], 255);
+    }
+
+    #[test]
+    fn elliptical_anti_aliasing_matches_circles() {
+        let mut circle = ImageBuffer::from_pixel(64, 64, OPAQUE);
+        border_radius(&mut circle, 30, |x, y| (x - 1, y - 1));
+        let mut ellipse = ImageBuffer::from_pixel(64, 64, OPAQUE);
+        elliptical_border_radius(&mut ellipse, 30, 30, |x, y| (x - 1, y - 1));
+
+        // Both are anti-aliased, but [`border_radius`] only approximates the coverage of the edge pixels.
+        for ((x, y, circle), ellipse) in circle.enumerate_pixels().zip(ellipse.pixels()) {
+            let (circle, ellipse) = (circle.0[3], ellipse.0[3]);
+            assert!(
+                circle.abs_diff(ellipse) <= 10,
+                "({x}, {y}): {circle} != {ellipse}"
+            );
+        }
+    }
+
+    #[test]
+    fn clamps_elliptical_radii_per_axis() {
+        // The vertical radii on the left need 150 of 100 pixels, so everything is scaled by 2/3.
+        let corners = [Some(radius("30 / 90")), None, Some(radius("30 / 60")), None];
+        let radii = corner_radii(100, 100, &params_with(corners, RadiusOverflow::Clamp)).unwrap();
+        assert_eq!(radii, ((20, 60), (0, 0), (20, 40), (0, 0)));
+
+        let result = corner_radii(100, 100, &params_with(corners, RadiusOverflow::Strict));
+        assert!(
+            matches!(result, Err(ApiError::Any(StatusCode::BAD_REQUEST, message)) if message.contains("left"))
+        );
+    }
+}
@@ -1,58 +1,107 @@
+mod decoration;
 pub(super) mod logic;
+mod radius;
 
+use super::{
+    output::{EncodedImage, ImageOutput, OutputQueryParams},
+    source::{ImageUploadForm, ImageUrlQueryParams},
+};
 use crate::{
+    animation::animation_from_bytes,
     error::ApiError,
-    extract::Query,
-    utils::{fetch_raw_image, image_from_bytes},
+    extract::{ImageUpload, Query},
+    fetch::fetch_raw_image,
+    state::AppState,
+    utils::DecodeLimits,
 };
-use axum::{http::header, response::AppendHeaders};
-use image::ImageFormat;
-use logic::round;
+use axum::{body::Bytes, extract::State};
+pub use decoration::DecorationQueryParams;
+use logic::{corner_radii, cut_corners};
+pub use radius::CornerRadius;
 use serde::{Deserialize, Serialize};
-use serde_default_utils::default_u32;
-use std::{io::Cursor, result::Result as StdResult};
-use utoipa::IntoParams;
+use std::result::Result as StdResult;
+use utoipa::{IntoParams, ToSchema};
+
+mod defaults {
+    use super::CornerRadius;
+
+    #[inline(always)]
+    pub fn corner_radius() -> CornerRadius {
+        CornerRadius::pixels(3)
+    }
+}
+
+/// What happens if two corners on the same side have a larger radius than the side is long.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RadiusOverflow {
+    /// Every radius is scaled down by the same factor until they fit, like CSS `border-radius`
+    #[default]
+    Clamp,
+    /// The request fails with a 400 naming the corners that don't fit
+    Strict,
+}
 
 #[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct RoundImageQueryParams {
-    /// The URL to the image that should be rounded
-    pub url: String,
-
     #[serde(default)]
     /// Whether the API tries to figure out the max. radius on its own. This means if width and height are the same, you'll get a perfectly round image. This will override everything else
     pub auto: bool,
 
-    #[serde(default = "default_u32::<3>")]
-    /// The radius to use when rounding the corners. Specifying specific corners overrides this for said corner.
-    corner_radius: u32,
+    #[serde(default = "defaults::corner_radius")]
+    #[param(value_type = String, default = "3")]
+    /// The radius to use when rounding the corners, in pixels (`20`), in percent of the shorter side (`20%`) or as the horizontal and vertical radius of an ellipse (`20% / 10%`). Specifying specific corners overrides this for said corner.
+    corner_radius: CornerRadius,
+
+    #[param(value_type = Option<String>)]
+    top_left: Option<CornerRadius>,
 
-    top_left: Option<u32>,
+    #[param(value_type = Option<String>)]
+    top_right: Option<CornerRadius>,
 
-    top_right: Option<u32>,
+    #[param(value_type = Option<String>)]
+    bottom_left: Option<CornerRadius>,
 
-    bottom_left: Option<u32>,
+    #[param(value_type = Option<String>)]
+    bottom_right: Option<CornerRadius>,
 
-    bottom_right: Option<u32>,
+    #[serde(default)]
+    #[param(inline)]
+    overflow: RadiusOverflow,
 }
 
 impl RoundImageQueryParams {
-    pub fn top_left(&self) -> u32 {
+    /// Rounds every corner by `corner_radius`, or as much as possible with `auto`.
+    pub fn uniform(corner_radius: u32, auto: bool) -> Self {
+        Self {
+            auto,
+            corner_radius: CornerRadius::pixels(corner_radius),
+            top_left: None,
+            top_right: None,
+            bottom_left: None,
+            bottom_right: None,
+            overflow: RadiusOverflow::Clamp,
+        }
+    }
+
+    pub fn top_left(&self) -> CornerRadius {
         self.top_left.unwrap_or(self.corner_radius)
     }
 
-    pub fn top_right(&self) -> u32 {
+    pub fn top_right(&self) -> CornerRadius {
         self.top_right.unwrap_or(self.corner_radius)
     }
 
-    pub fn bottom_left(&self) -> u32 {
+    pub fn bottom_left(&self) -> CornerRadius {
         self.bottom_left.unwrap_or(self.corner_radius)
     }
 
-    pub fn bottom_right(&self) -> u32 {
+    pub fn bottom_right(&self) -> CornerRadius {
         self.bottom_right.unwrap_or(self.corner_radius)
     }
 
-    pub fn list_corners(&self) -> (u32, u32, u32, u32) {
+    pub fn list_corners(&self) -> (CornerRadius, CornerRadius, CornerRadius, CornerRadius) {
         (
             self.top_left(),
             self.top_right(),
@@ -62,32 +111,78 @@ impl RoundImageQueryParams {
     }
 }
 
+type RoundImageResponse = StdResult<EncodedImage, ApiError>;
+
 #[utoipa::path(
     get,
     path = "/round",
-    params(RoundImageQueryParams),
+    params(ImageUrlQueryParams, RoundImageQueryParams, DecorationQueryParams, OutputQueryParams),
     responses(
-        (status = 200, content_type = "image/png", description = "The raw image")
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
     )
 )]
 pub async fn round_image(
+    State(state): State<AppState>,
+    Query(source): Query<ImageUrlQueryParams>,
     Query(round_image_params): Query<RoundImageQueryParams>,
-) -> StdResult<
-    (
-        AppendHeaders<[(header::HeaderName, &'static str); 1]>,
-        Vec<u8>,
-    ),
-    ApiError,
-> {
-    let bytes = fetch_raw_image(&round_image_params.url).await?;
-
-    let mut img = image_from_bytes(bytes)?;
-
-    round(&mut img, round_image_params)?;
-
-    let mut buffer: Vec<u8> = Vec::new();
+    Query(decoration): Query<DecorationQueryParams>,
+    output: ImageOutput,
+) -> RoundImageResponse {
+    let bytes = fetch_raw_image(&state, &source.url).await?;
+
+    state
+        .workers
+        .run(move || round_image_bytes(bytes, round_image_params, decoration, output))
+        .await?
+}
 
-    img.write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)?;
+#[utoipa::path(
+    post,
+    path = "/round",
+    params(RoundImageQueryParams, DecorationQueryParams, OutputQueryParams),
+    request_body(
+        content = inline(ImageUploadForm),
+        content_type = ["multipart/form-data", "image/*"],
+        description = "The image that should be rounded (max. 3mb by default)"
+    ),
+    responses(
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
+    )
+)]
+pub async fn round_image_upload(
+    State(state): State<AppState>,
+    Query(round_image_params): Query<RoundImageQueryParams>,
+    Query(decoration): Query<DecorationQueryParams>,
+    output: ImageOutput,
+    ImageUpload(bytes): ImageUpload,
+) -> RoundImageResponse {
+    state
+        .workers
+        .run(move || round_image_bytes(bytes, round_image_params, decoration, output))
+        .await?
+}
 
-    Ok((AppendHeaders([(header::CONTENT_TYPE, "image/png")]), buffer))
+fn round_image_bytes(
+    bytes: Bytes,
+    round_image_params: RoundImageQueryParams,
+    decoration: DecorationQueryParams,
+    output: ImageOutput,
+) -> RoundImageResponse {
+    let animation = animation_from_bytes(bytes)?;
+
+    let (width, height) = animation.dimensions();
+    let radii = corner_radii(width, height, &round_image_params)?;
+    let decoration = decoration.prepare((width, height), radii)?;
+    // The frames were only checked at their size before the decoration.
+    let (canvas_width, canvas_height) = decoration.dimensions();
+    DecodeLimits::from_config().check_animation(
+        animation.frames.len() as u64,
+        canvas_width as u64 * canvas_height as u64,
+    )?;
+    let animation = animation.try_map(|mut img| {
+        cut_corners(&mut img, radii);
+        Ok(decoration.apply(img))
+    })?;
+
+    output.encode_animation(animation)
 }
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// A length in pixels, or in percent of the shorter side of the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Length {
+    Pixels(u32),
+    Percent(f32),
+}
+
+impl Length {
+    fn resolve(self, shorter_side: u32) -> u32 {
+        match self {
+            Self::Pixels(pixels) => pixels,
+            Self::Percent(percent) => (shorter_side as f64 * percent as f64 / 100.0).round() as u32,
+        }
+    }
+}
+
+impl FromStr for Length {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+
+        if let Some(percent) = input.strip_suffix('%') {
+            let percent: f32 = percent.trim_end().parse().map_err(|_| ())?;
+            if !percent.is_finite() || percent < 0.0 {
+                return Err(());
+            }
+            return Ok(Self::Percent(percent));
+        }
+
+        let pixels = input.strip_suffix("px").unwrap_or(input).trim_end();
+        pixels.parse().map(Self::Pixels).map_err(|_| ())
+    }
+}
+
+impl fmt::Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pixels(pixels) => write!(f, "{pixels}"),
+            Self::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+/// The radius of a corner like `20`, `20px` or `20%`, or an elliptical one like `20% / 10%` with the horizontal radius first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CornerRadius {
+    horizontal: Length,
+    vertical: Length,
+}
+
+impl CornerRadius {
+    pub const fn pixels(radius: u32) -> Self {
+        Self {
+            horizontal: Length::Pixels(radius),
+            vertical: Length::Pixels(radius),
+        }
+    }
+
+    /// The `(horizontal, vertical)` radius in pixels for an image of the given size.
+    pub fn resolve(self, width: u32, height: u32) -> (u32, u32) {
+        let shorter_side = width.min(height);
+        (
+            self.horizontal.resolve(shorter_side),
+            self.vertical.resolve(shorter_side),
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("`{0}` is not a valid radius, use pixels like `20`, a percentage like `20%` or both radii of an ellipse like `20% / 10%`.")]
+pub struct ParseRadiusError(String);
+
+impl FromStr for CornerRadius {
+    type Err = ParseRadiusError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let error = || ParseRadiusError(input.to_owned());
+
+        let (horizontal, vertical) = match input.split_once('/') {
+            Some((horizontal, vertical)) => (horizontal, vertical),
+            None => (input, input),
+        };
+
+        Ok(Self {
+            horizontal: horizontal.parse().map_err(|_| error())?,
+            vertical: vertical.parse().map_err(|_| error())?,
+        })
+    }
+}
+
+impl fmt::Display for CornerRadius {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.horizontal == self.vertical {
+            write!(f, "{}", self.horizontal)
+        } else {
+            write!(f, "{} / {}", self.horizontal, self.vertical)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for CornerRadius {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let radius = String::deserialize(deserializer)?;
+        radius.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for CornerRadius {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
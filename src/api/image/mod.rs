@@ -1,23 +1,59 @@
 pub mod captcha;
+pub mod gradient;
+pub mod image_mask;
 pub mod image_round;
+pub mod palette_image;
 pub mod preview_color;
-use axum::{routing::get, Router};
-pub use captcha::{generate_captcha_image, generate_captcha_response};
-use dominant_colors::dominant_colors;
-pub use image_round::round_image;
+use crate::{config::CONFIG, state::AppState};
+use axum::{extract::DefaultBodyLimit, routing::get, Router};
+pub use captcha::{captcha_challenge_image, generate_captcha_image, generate_captcha_response};
+use dominant_colors::{dominant_colors, dominant_colors_upload};
+pub use gradient::gradient;
+pub use image_mask::{mask_image, mask_image_upload};
+pub use image_round::{round_image, round_image_upload};
+pub use palette_image::palette_image;
 pub use preview_color::preview_color;
+mod colorspace;
 mod dominant_colors;
+mod font;
 mod hex_color;
+mod output;
+mod source;
+mod webp_animation;
 
 mod docs {
-    use super::{captcha::*, dominant_colors::*, image_round::*, preview_color::*};
+    use super::{
+        captcha::*, colorspace::*, dominant_colors::*, gradient::*, image_mask::*, image_round::*,
+        palette_image::*, preview_color::*,
+    };
     use preview_size::PreviewSize;
     use utoipa::OpenApi;
 
     #[derive(OpenApi)]
     #[openapi(
-        paths(preview_color, generate_captcha_image, round_image, dominant_colors),
-        components(schemas(PreviewSize))
+        paths(
+            preview_color,
+            gradient,
+            generate_captcha_image,
+            captcha_challenge_image,
+            round_image,
+            round_image_upload,
+            mask_image,
+            mask_image_upload,
+            dominant_colors,
+            dominant_colors_upload,
+            palette_image
+        ),
+        components(schemas(
+            PreviewSize,
+            DominantColorEntry,
+            NearestColor,
+            Rgb,
+            Hsl,
+            Hsv,
+            Cmyk,
+            Lab
+        ))
     )]
     pub struct ImageDocs;
 }
@@ -25,10 +61,19 @@ mod docs {
 pub use docs::ImageDocs;
 
 // Starts with `/image/{...}`
-pub fn router() -> Router {
+pub fn router() -> Router<AppState> {
     Router::new()
         .route("/gen_captcha", get(generate_captcha_image))
-        .route("/round", get(round_image))
+        .route("/captcha/:id", get(captcha_challenge_image))
+        .route("/round", get(round_image).post(round_image_upload))
+        .route("/mask", get(mask_image).post(mask_image_upload))
         .route("/colorpreview", get(preview_color))
-        .route("/dominant_colors", get(dominant_colors))
+        .route("/gradient", get(gradient))
+        .route("/palette", get(palette_image))
+        .route(
+            "/dominant_colors",
+            get(dominant_colors).post(dominant_colors_upload),
+        )
+        // leave some room for the multipart boundaries, the image itself is checked by `ImageUpload`
+        .layer(DefaultBodyLimit::max(CONFIG.max_image_bytes + 64 * 1024))
 }
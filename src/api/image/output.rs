@@ -0,0 +1,582 @@
+use super::webp_animation;
+use crate::{animation::Animation, error::ApiError, extract::Query};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use image::{
+    codecs::{
+        gif::{GifEncoder, Repeat},
+        jpeg::JpegEncoder,
+        png::PngEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
+    ColorType, DynamicImage, Frame, ImageEncoder,
+};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+/// An image format the API can respond with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Png,
+    Webp,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Avif,
+    Gif,
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        use OutputFormat::*;
+
+        match self {
+            Png => "image/png",
+            Webp => "image/webp",
+            Jpeg => "image/jpeg",
+            Avif => "image/avif",
+            Gif => "image/gif",
+        }
+    }
+
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        use OutputFormat::*;
+
+        match mime {
+            "image/png" => Some(Png),
+            "image/webp" => Some(Webp),
+            "image/jpeg" | "image/jpg" => Some(Jpeg),
+            "image/avif" => Some(Avif),
+            "image/gif" => Some(Gif),
+            _ => None,
+        }
+    }
+
+    /// Whether the format can store (at least some) transparency.
+    pub fn supports_alpha(self) -> bool {
+        !matches!(self, OutputFormat::Jpeg)
+    }
+
+    /// Whether the format can store more than one frame, PNG as APNG.
+    pub fn supports_animation(self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Png | OutputFormat::Webp | OutputFormat::Gif
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OutputQueryParams {
+    /// The format of the returned image. Takes precedence over the `Accept` header, defaults to PNG if neither is given.
+    /// Animated images stay animated as PNG (APNG), WebP or GIF, JPEG and AVIF only get their first frame.
+    #[param(inline)]
+    format: Option<OutputFormat>,
+
+    /// The quality of lossy formats (JPEG, WebP, AVIF) from 1 to 100. WebP is encoded lossless if omitted.
+    #[param(minimum = 1, maximum = 100)]
+    quality: Option<u8>,
+}
+
+/// The negotiated output of an image endpoint, built from the `format` and `quality` query params and the `Accept` header.
+#[derive(Debug)]
+pub struct ImageOutput {
+    /// The acceptable formats, most preferred first. Never empty.
+    candidates: Vec<OutputFormat>,
+    /// Whether the format was requested explicitly via the `format` query param.
+    explicit: bool,
+    quality: Option<u8>,
+}
+
+impl ImageOutput {
+    const DEFAULT_QUALITY: u8 = 80;
+    const AVIF_SPEED: u8 = 8;
+    /// Quantizing every frame at the default speed of 1 takes too long for longer animations.
+    const GIF_ANIMATION_SPEED: i32 = 10;
+
+    fn negotiate(headers: &HeaderMap) -> Vec<OutputFormat> {
+        let mut ranked: Vec<(OutputFormat, f32)> = Vec::new();
+        let mut wildcard = None;
+
+        let accept = headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for media_range in accept {
+            let mut parts = media_range.split(';').map(str::trim);
+            let mime = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if q <= 0.0 {
+                continue;
+            }
+
+            if let Some(format) = OutputFormat::from_mime(&mime) {
+                ranked.push((format, q));
+            } else if mime == "image/*" || mime == "*/*" {
+                wildcard = Some(wildcard.map_or(q, |w: f32| w.max(q)));
+            }
+        }
+
+        if let Some(q) = wildcard {
+            ranked.push((OutputFormat::Png, q));
+        }
+
+        // stable, so equally ranked formats keep the client's order
+        ranked.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut candidates: Vec<OutputFormat> = Vec::with_capacity(ranked.len());
+        for (format, _) in ranked {
+            if !candidates.contains(&format) {
+                candidates.push(format);
+            }
+        }
+
+        if candidates.is_empty() {
+            // nothing we can produce was asked for, so ignore the header like before
+            candidates.push(OutputFormat::Png);
+        }
+
+        candidates
+    }
+
+    /// Encodes `img` in the most preferred format that can represent it.
+    pub fn encode(&self, img: DynamicImage) -> Result<EncodedImage, ApiError> {
+        let transparent = img.color().has_alpha() && img.to_rgba8().pixels().any(|p| p.0[3] < 255);
+
+        let Some(&format) = self
+            .candidates
+            .iter()
+            .find(|format| format.supports_alpha() || !transparent)
+        else {
+            let msg = "JPEG cannot store the transparent pixels of this image, request png, webp, avif or gif instead.";
+            return Err(if self.explicit {
+                ApiError::AnyStatic(StatusCode::BAD_REQUEST, msg)
+            } else {
+                ApiError::AnyStatic(StatusCode::NOT_ACCEPTABLE, msg)
+            });
+        };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        let quality = self.quality.unwrap_or(Self::DEFAULT_QUALITY);
+
+        match format {
+            OutputFormat::Png => {
+                let img = img.to_rgba8();
+                PngEncoder::new(&mut bytes).write_image(
+                    &img,
+                    img.width(),
+                    img.height(),
+                    ColorType::Rgba8,
+                )?;
+            }
+            OutputFormat::Jpeg => {
+                let img = img.to_rgb8();
+                JpegEncoder::new_with_quality(&mut bytes, quality).write_image(
+                    &img,
+                    img.width(),
+                    img.height(),
+                    ColorType::Rgb8,
+                )?;
+            }
+            OutputFormat::Webp => {
+                let webp_quality = self
+                    .quality
+                    .map_or_else(WebPQuality::lossless, WebPQuality::lossy);
+                let img = img.to_rgba8();
+                WebPEncoder::new_with_quality(&mut bytes, webp_quality).write_image(
+                    &img,
+                    img.width(),
+                    img.height(),
+                    ColorType::Rgba8,
+                )?;
+            }
+            OutputFormat::Gif => {
+                GifEncoder::new(&mut bytes).encode_frame(Frame::new(img.to_rgba8()))?;
+            }
+            OutputFormat::Avif => {
+                let img = img.to_rgba8();
+                let pixels: Vec<ravif::RGBA8> = img
+                    .pixels()
+                    .map(|p| ravif::RGBA8::new(p.0[0], p.0[1], p.0[2], p.0[3]))
+                    .collect();
+
+                bytes = ravif::Encoder::new()
+                    .with_quality(quality as f32)
+                    .with_alpha_quality(quality as f32)
+                    .with_speed(Self::AVIF_SPEED)
+                    .encode_rgba(ravif::Img::new(
+                        &pixels[..],
+                        img.width() as usize,
+                        img.height() as usize,
+                    ))
+                    .map_err(|err| {
+                        ApiError::Any(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+                    })?
+                    .avif_file;
+            }
+        }
+
+        Ok(EncodedImage { format, bytes })
+    }
+
+    /// Encodes every frame of `animation` in the most preferred format that can be animated, or only its first frame if
+    /// none of the acceptable formats can.
+    pub fn encode_animation(&self, animation: Animation) -> Result<EncodedImage, ApiError> {
+        let Some(&format) = self
+            .candidates
+            .iter()
+            .find(|format| format.supports_animation())
+            .filter(|_| animation.is_animated())
+        else {
+            let first = animation.frames.into_iter().next().unwrap().into_buffer();
+            return self.encode(DynamicImage::ImageRgba8(first));
+        };
+
+        let (width, height) = animation.dimensions();
+        let delays_ms = animation.frames.iter().map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            numer as f64 / denom as f64
+        });
+        let internal_error = |err: &dyn std::fmt::Display| {
+            ApiError::Any(StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+
+        match format {
+            OutputFormat::Png => {
+                let mut encoder = png::Encoder::new(&mut bytes, width, height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder
+                    .set_animated(animation.frames.len() as u32, animation.plays)
+                    .map_err(|err| internal_error(&err))?;
+
+                let mut writer = encoder.write_header().map_err(|err| internal_error(&err))?;
+                for (frame, delay) in animation.frames.iter().zip(delays_ms) {
+                    writer
+                        .set_frame_delay(delay.round().min(u16::MAX as f64) as u16, 1000)
+                        .and_then(|_| writer.write_image_data(frame.buffer()))
+                        .map_err(|err| internal_error(&err))?;
+                }
+                writer.finish().map_err(|err| internal_error(&err))?;
+            }
+            OutputFormat::Webp => bytes = webp_animation::encode(&animation, self.quality)?,
+            OutputFormat::Gif => {
+                let mut encoder = GifEncoder::new_with_speed(&mut bytes, Self::GIF_ANIMATION_SPEED);
+                match animation.plays {
+                    0 => encoder.set_repeat(Repeat::Infinite)?,
+                    1 => {}
+                    plays => encoder
+                        .set_repeat(Repeat::Finite((plays - 1).min(u16::MAX as u32) as u16))?,
+                }
+                encoder.encode_frames(animation.frames)?;
+            }
+            OutputFormat::Jpeg | OutputFormat::Avif => {
+                unreachable!("JPEG and AVIF can't be animated")
+            }
+        }
+
+        Ok(EncodedImage { format, bytes })
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ImageOutput
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::<OutputQueryParams>::from_request_parts(parts, state).await?;
+
+        if let Some(quality) = params.quality {
+            if !(1..=100).contains(&quality) {
+                return Err(ApiError::AnyStatic(
+                    StatusCode::BAD_REQUEST,
+                    "The quality must be in between 1 and 100.",
+                ));
+            }
+        }
+
+        let (candidates, explicit) = match params.format {
+            Some(format) => (vec![format], true),
+            None => (Self::negotiate(&parts.headers), false),
+        };
+
+        Ok(Self {
+            candidates,
+            explicit,
+            quality: params.quality,
+        })
+    }
+}
+
+/// An encoded image, responded with the matching `Content-Type`.
+pub struct EncodedImage {
+    format: OutputFormat,
+    bytes: Vec<u8>,
+}
+
+impl IntoResponse for EncodedImage {
+    fn into_response(self) -> Response {
+        (
+            [
+                (header::CONTENT_TYPE, self.format.content_type()),
+                (header::VARY, "accept"),
+            ],
+            self.bytes,
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{animation::decode_animation_with_limits, utils::DecodeLimits};
+    use axum::http::Request;
+    use image::{Delay, Rgba, RgbaImage};
+    use OutputFormat::*;
+
+    fn candidates(accept: &str) -> Vec<OutputFormat> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, accept.parse().unwrap());
+        ImageOutput::negotiate(&headers)
+    }
+
+    async fn output(uri: &str, accept: &str) -> Result<ImageOutput, ApiError> {
+        let request = Request::builder()
+            .uri(uri)
+            .header(header::ACCEPT, accept)
+            .body(())
+            .unwrap();
+        ImageOutput::from_request_parts(&mut request.into_parts().0, &()).await
+    }
+
+    fn explicit(format: OutputFormat) -> ImageOutput {
+        ImageOutput {
+            candidates: vec![format],
+            explicit: true,
+            quality: Some(60),
+        }
+    }
+
+    fn image(alpha: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 4, Rgba([255, 0, 0, alpha])))
+    }
+
+    #[test]
+    fn negotiates_by_quality() {
+        assert_eq!(candidates("image/avif,image/*;q=0.8"), [Avif, Png]);
+        assert_eq!(candidates("image/webp;q=0.5, image/jpeg"), [Jpeg, Webp]);
+        assert_eq!(candidates("image/*;q=0.9, image/gif;q=0.2"), [Png, Gif]);
+        // equally ranked formats keep the client's order
+        assert_eq!(candidates("image/gif, IMAGE/WEBP"), [Gif, Webp]);
+        assert_eq!(candidates("image/jpg, image/jpeg;q=0.5"), [Jpeg]);
+    }
+
+    #[test]
+    fn ignores_formats_that_cant_be_produced() {
+        assert_eq!(candidates("image/png;q=0, image/webp"), [Webp]);
+        assert_eq!(candidates("text/html, image/bmp"), [Png]);
+        assert_eq!(candidates("image/webp;q=0"), [Png]);
+        assert_eq!(candidates("*/*"), [Png]);
+        assert_eq!(ImageOutput::negotiate(&HeaderMap::new()), [Png]);
+    }
+
+    #[tokio::test]
+    async fn prefers_the_format_param() {
+        let output = output("/?format=jpg&quality=50", "image/webp")
+            .await
+            .unwrap();
+
+        assert_eq!(output.candidates, [Jpeg]);
+        assert!(output.explicit);
+        assert_eq!(output.quality, Some(50));
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_formats_and_qualities() {
+        for (uri, message) in [
+            ("/?format=bmp", "unknown variant `bmp`"),
+            ("/?quality=0", "between 1 and 100"),
+            ("/?quality=101", "between 1 and 100"),
+        ] {
+            let response = output(uri, "image/png").await.unwrap_err().into_response();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+
+            assert!(
+                String::from_utf8_lossy(&body).contains(message),
+                "{uri}: {body:?}"
+            );
+        }
+
+        for uri in ["/?format=bmp", "/?quality=0"] {
+            let response = output(uri, "image/png").await.unwrap_err().into_response();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[test]
+    fn skips_jpeg_for_transparent_images() {
+        let negotiated = |candidates| ImageOutput {
+            candidates,
+            explicit: false,
+            quality: None,
+        };
+
+        let encoded = negotiated(vec![Jpeg, Webp]).encode(image(128)).unwrap();
+        assert_eq!(encoded.format, Webp);
+        let encoded = negotiated(vec![Jpeg, Webp]).encode(image(255)).unwrap();
+        assert_eq!(encoded.format, Jpeg);
+
+        let status = |result: Result<EncodedImage, ApiError>| match result {
+            Err(err) => err.into_response().status(),
+            Ok(_) => StatusCode::OK,
+        };
+        // a negotiated format is not acceptable, a requested one is a bad request
+        assert_eq!(
+            status(negotiated(vec![Jpeg]).encode(image(128))),
+            StatusCode::NOT_ACCEPTABLE
+        );
+        assert_eq!(
+            status(explicit(Jpeg).encode(image(128))),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn encodes_every_format() {
+        for format in [Png, Webp, Jpeg, Avif, Gif] {
+            let encoded = explicit(format).encode(image(255)).unwrap();
+            assert_eq!(encoded.format, format);
+
+            match format {
+                // `image` can't read AVIF, but it's recognizable by its brand
+                Avif => assert_eq!(&encoded.bytes[4..12], b"ftypavif"),
+                _ => {
+                    let decoded = image::load_from_memory(&encoded.bytes).unwrap();
+                    assert_eq!((decoded.width(), decoded.height()), (8, 4), "{format:?}");
+                    assert_eq!(
+                        image::guess_format(&encoded.bytes).unwrap().to_mime_type(),
+                        format.content_type()
+                    );
+                }
+            }
+
+            let response = encoded.into_response();
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                format.content_type()
+            );
+            assert_eq!(response.headers()[header::VARY], "accept");
+        }
+    }
+
+    const LIMITS: DecodeLimits = DecodeLimits {
+        max_width: 1024,
+        max_height: 1024,
+        max_pixels: 512 * 512,
+        max_alloc: 16 * 1024 * 1024,
+        max_frames: 8,
+        max_animation_pixels: 64 * 64 * 8,
+    };
+    const COLORS: [[u8; 4]; 3] = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]];
+    const DELAYS_MS: [u32; 3] = [40, 70, 250];
+
+    fn animation(plays: u32) -> Animation {
+        let frames = COLORS
+            .iter()
+            .zip(DELAYS_MS)
+            .map(|(&color, delay)| {
+                Frame::from_parts(
+                    RgbaImage::from_pixel(32, 32, Rgba(color)),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay, 1),
+                )
+            })
+            .collect();
+
+        Animation { frames, plays }
+    }
+
+    fn round_trip(format: OutputFormat, plays: u32) -> Animation {
+        let output = ImageOutput {
+            candidates: vec![format],
+            explicit: true,
+            quality: None,
+        };
+        let encoded = output.encode_animation(animation(plays)).unwrap();
+        assert_eq!(encoded.format, format);
+
+        decode_animation_with_limits(encoded.bytes.into(), LIMITS).unwrap()
+    }
+
+    fn assert_round_trips(format: OutputFormat) {
+        for plays in [0, 1, 3] {
+            let decoded = round_trip(format, plays);
+
+            let delays_ms: Vec<u32> = decoded
+                .frames
+                .iter()
+                .map(|frame| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    numer / denom
+                })
+                .collect();
+            let colors: Vec<[u8; 4]> = decoded
+                .frames
+                .iter()
+                .map(|frame| frame.buffer().get_pixel(16, 16).0)
+                .collect();
+
+            assert_eq!(delays_ms, DELAYS_MS, "{format:?}");
+            assert_eq!(colors, COLORS, "{format:?}");
+            assert_eq!(decoded.plays, plays, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn round_trips_apng() {
+        assert_round_trips(OutputFormat::Png);
+    }
+
+    #[test]
+    fn round_trips_webp() {
+        assert_round_trips(OutputFormat::Webp);
+    }
+
+    #[test]
+    fn round_trips_gif() {
+        assert_round_trips(OutputFormat::Gif);
+    }
+
+    #[test]
+    fn encodes_the_first_frame_of_formats_without_animations() {
+        let output = ImageOutput {
+            candidates: vec![OutputFormat::Jpeg],
+            explicit: true,
+            quality: None,
+        };
+        let encoded = output.encode_animation(animation(0)).unwrap();
+        let decoded = decode_animation_with_limits(encoded.bytes.into(), LIMITS).unwrap();
+
+        assert!(!decoded.is_animated());
+        assert!(decoded.frames[0].buffer().get_pixel(16, 16).0[0] > 200);
+    }
+}
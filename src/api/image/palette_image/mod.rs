@@ -0,0 +1,401 @@
+use super::{
+    colorspace::{nearest_named_color, readable_text_color},
+    dominant_colors::{analyze, DominantColorQueryParams},
+    font::draw_centered_lines,
+    hex_color::{split_colors, HexColor},
+    output::{EncodedImage, ImageOutput, OutputQueryParams},
+    preview_color::preview_dimensions,
+};
+use crate::{error::ApiError, extract::Query, fetch::fetch_raw_image, state::AppState};
+use axum::{body::Bytes, extract::State, http::StatusCode};
+use image::{DynamicImage, ImageBuffer, Rgba, RgbaImage};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+/// The most colors a palette image may show.
+const MAX_SWATCHES: usize = 256;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteLayout {
+    /// One row of swatches
+    #[default]
+    Strip,
+    /// Rows of equally sized swatches, see `columns`
+    Grid,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteLabels {
+    #[default]
+    None,
+    Hex,
+    /// The exact or nearest color name
+    Name,
+    Both,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PaletteImageQueryParams {
+    /// Comma separated colors to render, e.g. `#ff8800, rgb(0 0 255), teal`
+    colors: Option<String>,
+
+    /// An image whose dominant colors are rendered instead of `colors`, analyzed with the parameters of `/dominant_colors`
+    url: Option<String>,
+
+    #[serde(default)]
+    #[param(inline)]
+    layout: PaletteLayout,
+
+    /// The number of columns of a grid, defaults to a roughly square grid
+    #[param(minimum = 1)]
+    columns: Option<u32>,
+
+    /// Sizes the swatches of a strip by their share of the image's pixels
+    #[serde(default)]
+    proportional: bool,
+
+    /// Labels that don't fit their swatch at a readable size are left out, `both` falls back to the hex code first
+    #[serde(default)]
+    #[param(inline)]
+    labels: PaletteLabels,
+
+    /// The width in pixels, defaults to 512
+    #[param(minimum = 1)]
+    width: Option<u32>,
+
+    /// The height in pixels, defaults to 128 for a strip and square swatches for a grid
+    #[param(minimum = 1)]
+    height: Option<u32>,
+}
+
+enum PaletteSource {
+    Colors(Vec<HexColor>),
+    Image(Bytes),
+}
+
+#[utoipa::path(
+    get,
+    path = "/palette",
+    params(PaletteImageQueryParams, DominantColorQueryParams, OutputQueryParams),
+    responses(
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
+    )
+)]
+pub async fn palette_image(
+    State(state): State<AppState>,
+    Query(params): Query<PaletteImageQueryParams>,
+    Query(analysis): Query<DominantColorQueryParams>,
+    output: ImageOutput,
+) -> Result<EncodedImage, ApiError> {
+    let source = match (&params.colors, &params.url) {
+        (Some(colors), None) => PaletteSource::Colors(
+            split_colors(colors)
+                .into_iter()
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+        ),
+        (None, Some(url)) => PaletteSource::Image(fetch_raw_image(&state, url).await?),
+        _ => {
+            return Err(ApiError::AnyStatic(
+                StatusCode::BAD_REQUEST,
+                "Either `colors` or `url` has to be set.",
+            ))
+        }
+    };
+
+    state
+        .workers
+        .run(move || {
+            let swatches: Vec<(HexColor, f32)> = match source {
+                PaletteSource::Colors(colors) => {
+                    colors.into_iter().map(|color| (color, 1.0)).collect()
+                }
+                PaletteSource::Image(bytes) => analyze(bytes, analysis)?
+                    .1
+                    .into_iter()
+                    .map(|swatch| (HexColor::opaque(swatch.rgb), swatch.count as f32))
+                    .collect(),
+            };
+
+            if swatches.is_empty() {
+                return Err(ApiError::AnyStatic(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "There are no colors to render.",
+                ));
+            }
+            if swatches.len() > MAX_SWATCHES {
+                return Err(ApiError::Any(
+                    StatusCode::BAD_REQUEST,
+                    format!("A palette can show at most {MAX_SWATCHES} colors."),
+                ));
+            }
+
+            let img = render(&swatches, &params)?;
+            output.encode(DynamicImage::ImageRgba8(img))
+        })
+        .await?
+}
+
+/// Resolves the requested `width` and `height`, a missing side defaults to its own side of `default`.
+fn dimensions(
+    params: &PaletteImageQueryParams,
+    (default_width, default_height): (u32, u32),
+) -> Result<(u32, u32), ApiError> {
+    preview_dimensions(
+        Some(params.width.unwrap_or(default_width)),
+        Some(params.height.unwrap_or(default_height)),
+        (default_width, default_height),
+    )
+}
+
+/// The `(x, y, width, height)` of every swatch.
+fn cells(
+    swatches: &[(HexColor, f32)],
+    params: &PaletteImageQueryParams,
+) -> Result<Vec<(u32, u32, u32, u32)>, ApiError> {
+    let count = swatches.len() as u32;
+
+    Ok(match params.layout {
+        PaletteLayout::Strip => {
+            let (width, height) = dimensions(params, (512, 128))?;
+            let total: f32 = if params.proportional {
+                swatches.iter().map(|(_, weight)| weight).sum()
+            } else {
+                count as f32
+            };
+
+            let mut covered = 0.0;
+            swatches
+                .iter()
+                .map(|(_, weight)| {
+                    let weight = if params.proportional { *weight } else { 1.0 };
+                    let start = (width as f32 * covered / total).round() as u32;
+                    covered += weight;
+                    let end = (width as f32 * covered / total).round() as u32;
+                    (start, 0, end - start, height)
+                })
+                .collect()
+        }
+        PaletteLayout::Grid => {
+            let columns = match params.columns {
+                Some(0) => {
+                    return Err(ApiError::AnyStatic(
+                        StatusCode::BAD_REQUEST,
+                        "`columns` must be at least 1.",
+                    ))
+                }
+                Some(columns) => columns.min(count),
+                None => (count as f32).sqrt().ceil() as u32,
+            };
+            let rows = count.div_ceil(columns);
+
+            // Square swatches, at the requested width if there is one.
+            let default_width = params.width.unwrap_or(512);
+            let default_height = (default_width.saturating_mul(rows) / columns).max(1);
+            let (width, height) = dimensions(params, (default_width, default_height))?;
+
+            let edge = |length: u32, index: u32, parts: u32| length * index / parts;
+            (0..count)
+                .map(|index| {
+                    let (column, row) = (index % columns, index / columns);
+                    let x = edge(width, column, columns);
+                    let y = edge(height, row, rows);
+                    (
+                        x,
+                        y,
+                        edge(width, column + 1, columns) - x,
+                        edge(height, row + 1, rows) - y,
+                    )
+                })
+                .collect()
+        }
+    })
+}
+
+fn render(
+    swatches: &[(HexColor, f32)],
+    params: &PaletteImageQueryParams,
+) -> Result<RgbaImage, ApiError> {
+    let cells = cells(swatches, params)?;
+
+    let (width, height) = cells.iter().fold((1, 1), |(width, height), (x, y, w, h)| {
+        (width.max(x + w), height.max(y + h))
+    });
+    let mut img: RgbaImage = ImageBuffer::new(width, height);
+
+    for ((color, _), &(x, y, w, h)) in swatches.iter().zip(&cells) {
+        for cell_y in y..y + h {
+            for cell_x in x..x + w {
+                img.put_pixel(cell_x, cell_y, Rgba(color.rgba()));
+            }
+        }
+
+        let hex = color.to_hex();
+        let name = || {
+            color_names::rgb_to_color_name(&color.rgb())
+                .unwrap_or_else(|| nearest_named_color(color.rgb()).0)
+        };
+        let lines: &[&str] = match params.labels {
+            PaletteLabels::None => continue,
+            PaletteLabels::Hex => &[&hex],
+            PaletteLabels::Name => &[name()],
+            PaletteLabels::Both => &[&hex, name()],
+        };
+
+        // A swatch too small for both lines gets only the hex code, one too small for any readable text none.
+        let text_color = Rgba(HexColor::opaque(readable_text_color(color.rgb())).rgba());
+        let cell = (x, y, w, h);
+        if !draw_centered_lines(&mut img, lines, text_color, cell, 24.0) && lines.len() > 1 {
+            draw_centered_lines(&mut img, &[&hex], text_color, cell, 24.0);
+        }
+    }
+
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(layout: PaletteLayout) -> PaletteImageQueryParams {
+        PaletteImageQueryParams {
+            colors: None,
+            url: None,
+            layout,
+            columns: None,
+            proportional: false,
+            labels: PaletteLabels::None,
+            width: None,
+            height: None,
+        }
+    }
+
+    fn swatches(weights: &[f32]) -> Vec<(HexColor, f32)> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(index, weight)| (HexColor::opaque([index as u8 * 40, 0, 0]), *weight))
+            .collect()
+    }
+
+    #[test]
+    fn proportional_strips_fill_the_width() {
+        let params = PaletteImageQueryParams {
+            proportional: true,
+            width: Some(100),
+            height: Some(10),
+            ..layout(PaletteLayout::Strip)
+        };
+
+        for weights in [
+            &[1.0, 1.0, 1.0][..],
+            &[3.0, 1.0],
+            &[0.7, 0.2, 0.05, 0.05],
+            &[1e6, 1.0],
+        ] {
+            let cells = cells(&swatches(weights), &params).unwrap();
+
+            // every swatch starts where the previous one ends
+            let mut x = 0;
+            for &(cell_x, y, width, height) in &cells {
+                assert_eq!((cell_x, y, height), (x, 0, 10), "{weights:?}");
+                x += width;
+            }
+            assert_eq!(x, 100, "{weights:?}");
+        }
+
+        let widths: Vec<u32> = cells(&swatches(&[3.0, 1.0]), &params)
+            .unwrap()
+            .iter()
+            .map(|cell| cell.2)
+            .collect();
+        assert_eq!(widths, [75, 25]);
+    }
+
+    #[test]
+    fn equal_strips_ignore_the_weights() {
+        let params = PaletteImageQueryParams {
+            width: Some(90),
+            ..layout(PaletteLayout::Strip)
+        };
+        let cells = cells(&swatches(&[5.0, 1.0, 1.0]), &params).unwrap();
+
+        assert_eq!(cells, [(0, 0, 30, 128), (30, 0, 30, 128), (60, 0, 30, 128)]);
+    }
+
+    #[test]
+    fn grids_have_square_swatches_by_default() {
+        // 5 colors make a 3x2 grid
+        let cells = cells(&swatches(&[1.0; 5]), &layout(PaletteLayout::Grid)).unwrap();
+
+        assert_eq!(cells.len(), 5);
+        assert_eq!(cells[0], (0, 0, 170, 170));
+        assert_eq!(cells[2], (341, 0, 171, 170));
+        assert_eq!(cells[3], (0, 170, 170, 171));
+
+        let img = render(&swatches(&[1.0; 5]), &layout(PaletteLayout::Grid)).unwrap();
+        assert_eq!(img.dimensions(), (512, 341));
+        // the last cell of the grid stays empty
+        assert_eq!(img[(511, 340)].0[3], 0);
+    }
+
+    #[test]
+    fn grids_follow_the_columns_and_size() {
+        let params = PaletteImageQueryParams {
+            columns: Some(2),
+            width: Some(100),
+            height: Some(30),
+            ..layout(PaletteLayout::Grid)
+        };
+        assert_eq!(
+            cells(&swatches(&[1.0; 6]), &params).unwrap(),
+            [
+                (0, 0, 50, 10),
+                (50, 0, 50, 10),
+                (0, 10, 50, 10),
+                (50, 10, 50, 10),
+                (0, 20, 50, 10),
+                (50, 20, 50, 10),
+            ]
+        );
+
+        let columns = |columns| PaletteImageQueryParams {
+            columns: Some(columns),
+            ..layout(PaletteLayout::Grid)
+        };
+        assert!(cells(&swatches(&[1.0; 6]), &columns(0)).is_err());
+        // more columns than colors make one row
+        assert_eq!(cells(&swatches(&[1.0; 3]), &columns(8)).unwrap()[2].1, 0);
+    }
+
+    #[test]
+    fn labels_only_what_fits() {
+        let labeled = |width, height, labels| {
+            let params = PaletteImageQueryParams {
+                labels,
+                width: Some(width),
+                height: Some(height),
+                ..layout(PaletteLayout::Strip)
+            };
+            let plain = PaletteImageQueryParams {
+                labels: PaletteLabels::None,
+                width: Some(width),
+                height: Some(height),
+                ..layout(PaletteLayout::Strip)
+            };
+            let colors = [(HexColor::opaque([0, 0, 0]), 1.0)];
+
+            render(&colors, &params).unwrap() != render(&colors, &plain).unwrap()
+        };
+
+        assert!(labeled(200, 80, PaletteLabels::Both));
+        // too low for two lines, the hex code still fits
+        assert!(labeled(200, 14, PaletteLabels::Both));
+        // too small for anything readable
+        assert!(!labeled(200, 6, PaletteLabels::Hex));
+        assert!(!labeled(20, 80, PaletteLabels::Name));
+    }
+}
@@ -1,60 +1,318 @@
 pub mod preview_size;
 
-use super::hex_color::HexColor;
-use crate::{error::ApiError, extract::Query};
-use axum::{http::header, response::AppendHeaders};
-use image::ImageFormat;
+use super::{
+    hex_color::{Checkerboard, HexColor},
+    image_round::{logic::round, RoundImageQueryParams},
+    output::{EncodedImage, ImageOutput, OutputQueryParams},
+};
+use crate::{config::CONFIG, error::ApiError, extract::Query, state::AppState};
+use axum::{extract::State, http::StatusCode};
+use image::{DynamicImage, RgbaImage};
 use preview_size::PreviewSize;
-use serde::{Deserialize, Serialize};
-use std::io::Cursor;
-use utoipa::IntoParams;
+use serde::Deserialize;
+use std::num::NonZeroU32;
+use utoipa::{IntoParams, ToSchema};
 
 mod defaults {
-    use super::preview_size::PreviewSize;
+    use super::{preview_size::PreviewSize, HexColor};
+    use std::num::NonZeroU32;
 
     #[inline(always)]
     pub fn preview_size() -> PreviewSize {
         PreviewSize::Small
     }
+
+    #[inline(always)]
+    pub fn corner_radius() -> u32 {
+        16
+    }
+
+    #[inline(always)]
+    pub fn checker_size() -> NonZeroU32 {
+        NonZeroU32::new(16).unwrap()
+    }
+
+    #[inline(always)]
+    pub fn checker_light() -> HexColor {
+        HexColor::opaque([255, 255, 255])
+    }
+
+    #[inline(always)]
+    pub fn checker_dark() -> HexColor {
+        HexColor::opaque([204, 204, 204])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewShape {
+    #[default]
+    Square,
+    /// A circle, or a pill if `width` and `height` differ
+    Circle,
+    /// A rectangle with rounded corners, see `corner_radius`
+    Rounded,
 }
 
-#[derive(Debug, Deserialize, Serialize, IntoParams)]
+#[derive(Debug, Deserialize, IntoParams)]
 #[into_params(parameter_in = Query)]
 pub struct PreviewColorQueryParams {
-    hex: String,
+    /// The color as hex (`#ff8800`, `f80`, `#ff880080`), `rgb()`, `hsl()`, `hwb()` or a color name
+    #[serde(alias = "color")]
+    #[param(value_type = String)]
+    hex: HexColor,
 
+    /// One of the preset sizes, ignored if `width` or `height` is set
     #[serde(default = "defaults::preview_size")]
     size: PreviewSize,
+
+    /// The width in pixels, defaults to `height` if only that is set
+    #[param(minimum = 1)]
+    width: Option<u32>,
+
+    /// The height in pixels, defaults to `width` if only that is set
+    #[param(minimum = 1)]
+    height: Option<u32>,
+
+    #[serde(default)]
+    #[param(inline)]
+    shape: PreviewShape,
+
+    /// The corner radius of the `rounded` shape, at most half of the shorter side is used
+    #[serde(default = "defaults::corner_radius")]
+    #[param(default = 16)]
+    corner_radius: u32,
+
+    /// Renders the color over a checkerboard, which makes transparency visible
+    #[serde(default)]
+    checkerboard: bool,
+
+    /// The size of a checkerboard square in pixels
+    #[serde(default = "defaults::checker_size")]
+    #[param(value_type = u32, minimum = 1, default = 16)]
+    checker_size: NonZeroU32,
+
+    /// The color of the light checkerboard squares
+    #[serde(default = "defaults::checker_light")]
+    #[param(value_type = String, default = "#ffffff")]
+    checker_light: HexColor,
+
+    /// The color of the dark checkerboard squares
+    #[serde(default = "defaults::checker_dark")]
+    #[param(value_type = String, default = "#cccccc")]
+    checker_dark: HexColor,
 }
 
-impl From<PreviewColorQueryParams> for (HexColor, PreviewSize) {
-    fn from(val: PreviewColorQueryParams) -> Self {
-        (HexColor::from(val.hex), val.size)
+impl PreviewColorQueryParams {
+    fn dimensions(&self) -> Result<(u32, u32), ApiError> {
+        preview_dimensions(self.width, self.height, self.size.into())
+    }
+
+    fn checkerboard(&self) -> Option<Checkerboard> {
+        self.checkerboard.then_some(Checkerboard {
+            size: self.checker_size.get(),
+            light: self.checker_light,
+            dark: self.checker_dark,
+        })
     }
 }
 
+/// Resolves the requested `width` and `height` of a generated image, a missing side defaults to the other one.
+pub(super) fn preview_dimensions(
+    width: Option<u32>,
+    height: Option<u32>,
+    default: (u32, u32),
+) -> Result<(u32, u32), ApiError> {
+    let (width, height) = match (width, height) {
+        (None, None) => return Ok(default),
+        (width, height) => (width.or(height).unwrap(), height.or(width).unwrap()),
+    };
+
+    let max = CONFIG.preview_max_size;
+    if !(1..=max).contains(&width) || !(1..=max).contains(&height) {
+        return Err(ApiError::Any(
+            StatusCode::BAD_REQUEST,
+            format!("`width` and `height` must be between 1 and {max}."),
+        ));
+    }
+
+    Ok((width, height))
+}
+
 #[utoipa::path(
     get,
     path = "/colorpreview", 
-    params(PreviewColorQueryParams),
+    params(PreviewColorQueryParams, OutputQueryParams),
     responses(
-        (status = 200, content_type = "image/png", description = "The raw image")
+        (status = 200, content_type = ["image/png", "image/webp", "image/jpeg", "image/avif", "image/gif"], description = "The raw image")
     )
 )]
 pub async fn preview_color(
+    State(state): State<AppState>,
     Query(params): Query<PreviewColorQueryParams>,
-) -> Result<
-    (
-        AppendHeaders<[(header::HeaderName, &'static str); 1]>,
-        Vec<u8>,
-    ),
-    ApiError,
-> {
-    let (hex, prevsize) = params.into();
-    let img = hex.into_preview(prevsize);
-
-    let mut buffer: Vec<u8> = Vec::new();
-    img.write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)?;
-
-    Ok((AppendHeaders([(header::CONTENT_TYPE, "image/png")]), buffer))
+    output: ImageOutput,
+) -> Result<EncodedImage, ApiError> {
+    let dimensions = params.dimensions()?;
+
+    state
+        .workers
+        .run(move || output.encode(DynamicImage::ImageRgba8(render(&params, dimensions)?)))
+        .await?
+}
+
+fn render(params: &PreviewColorQueryParams, dimensions: (u32, u32)) -> Result<RgbaImage, ApiError> {
+    let mut img = params.hex.into_preview(dimensions, params.checkerboard());
+
+    let short_side = dimensions.0.min(dimensions.1);
+    match params.shape {
+        PreviewShape::Square => {}
+        PreviewShape::Circle => round(&mut img, RoundImageQueryParams::uniform(0, true))?,
+        PreviewShape::Rounded => round(
+            &mut img,
+            RoundImageQueryParams::uniform(params.corner_radius.min(short_side / 2), false),
+        )?,
+    }
+
+    Ok(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::Query;
+    use axum::{extract::FromRequestParts, http::Request, response::IntoResponse};
+
+    async fn params(query: &str) -> Result<PreviewColorQueryParams, ApiError> {
+        let request = Request::builder()
+            .uri(format!("/image/colorpreview?{query}"))
+            .body(())
+            .unwrap();
+        let (mut parts, _) = request.into_parts();
+
+        Query::from_request_parts(&mut parts, &())
+            .await
+            .map(|Query(params)| params)
+    }
+
+    async fn preview(query: &str) -> RgbaImage {
+        let params = params(query).await.unwrap();
+        render(&params, params.dimensions().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn keeps_the_alpha_of_the_color() {
+        let img = preview("hex=%23ff880080").await;
+
+        assert_eq!(img.dimensions(), (128, 128));
+        assert!(img.pixels().all(|pixel| pixel.0 == [255, 136, 0, 128]));
+    }
+
+    #[tokio::test]
+    async fn renders_transparent_colors_over_a_checkerboard() {
+        let img = preview(
+            "hex=%23ff000080&checkerboard=true&checker_size=4&checker_light=white&checker_dark=black",
+        )
+        .await;
+
+        let (light, dark) = ([255, 127, 127, 255], [128, 0, 0, 255]);
+        assert_eq!(img[(0, 0)].0, light);
+        assert_eq!(img[(3, 3)].0, light);
+        assert_eq!(img[(4, 0)].0, dark);
+        assert_eq!(img[(0, 4)].0, dark);
+        assert_eq!(img[(4, 4)].0, light);
+    }
+
+    #[tokio::test]
+    async fn opaque_colors_cover_the_checkerboard() {
+        let img = preview("hex=teal&checkerboard=true").await;
+        assert!(img.pixels().all(|pixel| pixel.0 == [0, 128, 128, 255]));
+    }
+
+    async fn rejection(query: &str) -> String {
+        let err = match params(query).await {
+            Ok(params) => params.dimensions().unwrap_err(),
+            Err(err) => err,
+        };
+        let response = err.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query}");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        body["message"].as_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn sizes_the_preview() {
+        let max = CONFIG.preview_max_size;
+        let cases = [
+            (String::new(), (128, 128)),
+            ("&size=1".to_owned(), (256, 256)),
+            ("&size=2".to_owned(), (512, 512)),
+            ("&width=300".to_owned(), (300, 300)),
+            ("&height=20".to_owned(), (20, 20)),
+            ("&width=300&height=20&size=2".to_owned(), (300, 20)),
+            (format!("&width={max}&height=1"), (max, 1)),
+        ];
+
+        for (query, dimensions) in cases {
+            let params = params(&format!("hex=fff{query}")).await.unwrap();
+            assert_eq!(params.dimensions().unwrap(), dimensions, "{query}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_sizes_out_of_bounds() {
+        let max = CONFIG.preview_max_size;
+
+        for query in [
+            "hex=fff&width=0".to_owned(),
+            "hex=fff&height=0".to_owned(),
+            format!("hex=fff&width={}", max + 1),
+            format!("hex=fff&width=10&height={}", max + 1),
+        ] {
+            assert_eq!(
+                rejection(&query).await,
+                format!("`width` and `height` must be between 1 and {max}."),
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_preset_sizes() {
+        let message = rejection("hex=fff&size=3").await;
+        assert!(
+            message.contains(
+                "`3` is not a valid size, use 0 (128px), 1 (256px) or 2 (512px) or set `width` and `height`"
+            ),
+            "{message}"
+        );
+    }
+
+    #[tokio::test]
+    async fn cuts_the_shapes() {
+        let opaque = |img: &RgbaImage, x, y| img[(x, y)].0[3] == 255;
+
+        let square = preview("hex=red&width=64&height=32").await;
+        assert!(square.pixels().all(|pixel| pixel.0 == [255, 0, 0, 255]));
+
+        // a pill, the short side is the diameter of the ends
+        let circle = preview("hex=red&width=64&height=32&shape=circle").await;
+        assert!(!opaque(&circle, 0, 0) && !opaque(&circle, 63, 31));
+        assert!(!opaque(&circle, 2, 4));
+        assert!(opaque(&circle, 0, 16) && opaque(&circle, 63, 16));
+        assert!(opaque(&circle, 32, 0) && opaque(&circle, 32, 31));
+
+        let rounded = preview("hex=red&width=64&height=32&shape=rounded&corner_radius=8").await;
+        assert!(!opaque(&rounded, 0, 0) && !opaque(&rounded, 1, 1));
+        assert!(opaque(&rounded, 3, 3) && opaque(&rounded, 8, 0) && opaque(&rounded, 0, 8));
+
+        // the radius is limited to half the shorter side
+        let clamped = preview("hex=red&width=64&height=32&shape=rounded&corner_radius=100").await;
+        assert_eq!(
+            clamped,
+            preview("hex=red&width=64&height=32&shape=rounded&corner_radius=16").await
+        );
+    }
 }
@@ -1,24 +1,30 @@
-use serde_repr::{Deserialize_repr, Serialize_repr};
+use serde::Deserialize;
+use serde_repr::Serialize_repr;
 use utoipa::ToSchema;
 
-#[derive(Debug, Deserialize_repr, Serialize_repr, ToSchema)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize_repr, ToSchema)]
+#[serde(try_from = "u8")]
 #[repr(u8)]
-#[schema(default = 1)]
+#[schema(default = 0)]
 pub enum PreviewSize {
     Small,
     Medium,
     Large,
 }
 
-impl From<u8> for PreviewSize {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for PreviewSize {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         use PreviewSize::*;
 
         match value {
-            1 => Small,
-            2 => Medium,
-            3 => Large,
-            _ => Small,
+            0 => Ok(Small),
+            1 => Ok(Medium),
+            2 => Ok(Large),
+            _ => Err(format!(
+                "`{value}` is not a valid size, use 0 (128px), 1 (256px) or 2 (512px) or set `width` and `height`"
+            )),
         }
     }
 }
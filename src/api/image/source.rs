@@ -0,0 +1,17 @@
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImageUrlQueryParams {
+    /// The URL to the image that should be processed
+    pub url: String,
+}
+
+/// The `multipart/form-data` body of an image upload. A raw `image/*` body is accepted as well.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct ImageUploadForm {
+    #[schema(value_type = String, format = Binary)]
+    image: Vec<u8>,
+}
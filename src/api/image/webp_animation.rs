@@ -0,0 +1,147 @@
+//! Encodes animated WebPs with libwebp directly.
+//!
+//! `webp::AnimEncoder` ends every animation at the timestamp 0, so libwebp gave the last frame the average duration of
+//! the others. Here the animation ends after the last frame's own delay.
+
+use crate::{animation::Animation, error::ApiError};
+use axum::http::StatusCode;
+use libwebp_sys::*;
+use std::{ffi::CStr, mem::MaybeUninit, ptr, slice};
+
+/// A libwebp animation encoder, deleted when dropped.
+struct Encoder(*mut WebPAnimEncoder);
+
+impl Encoder {
+    fn new(width: u32, height: u32, plays: u32) -> Result<Self, ApiError> {
+        let encoder = unsafe {
+            let mut options = MaybeUninit::<WebPAnimEncoderOptions>::uninit();
+            if WebPAnimEncoderOptionsInitInternal(options.as_mut_ptr(), WebPGetMuxABIVersion()) == 0
+            {
+                return Err(internal_error("The WebP encoder couldn't be configured."));
+            }
+
+            let mut options = options.assume_init();
+            options.anim_params.loop_count = plays.min(u16::MAX as u32) as i32;
+            WebPAnimEncoderNewInternal(
+                width as i32,
+                height as i32,
+                &options,
+                WebPGetMuxABIVersion(),
+            )
+        };
+
+        if encoder.is_null() {
+            return Err(internal_error("The WebP encoder couldn't be created."));
+        }
+        Ok(Self(encoder))
+    }
+
+    /// The reason the last call to the encoder failed.
+    fn error(&self) -> ApiError {
+        let message = unsafe { CStr::from_ptr(WebPAnimEncoderGetError(self.0)) };
+        internal_error(message.to_string_lossy())
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { WebPAnimEncoderDelete(self.0) }
+    }
+}
+
+fn internal_error(message: impl Into<String>) -> ApiError {
+    ApiError::Any(StatusCode::INTERNAL_SERVER_ERROR, message.into())
+}
+
+/// Encodes every frame of `animation`, lossy with `quality` or lossless without.
+pub fn encode(animation: &Animation, quality: Option<u8>) -> Result<Vec<u8>, ApiError> {
+    let (width, height) = animation.dimensions();
+
+    let mut config = WebPConfig::new()
+        .map_err(|_| internal_error("The WebP encoder couldn't be configured."))?;
+    match quality {
+        Some(quality) => config.quality = quality as f32,
+        None => config.lossless = 1,
+    }
+
+    let encoder = Encoder::new(width, height, animation.plays)?;
+
+    // libwebp takes the time every frame starts at, and the end of the animation as a timestamp without a frame.
+    let mut timestamp = 0.0;
+    for frame in &animation.frames {
+        let mut picture = WebPPicture::new()
+            .map_err(|_| internal_error("The WebP frame couldn't be created."))?;
+        picture.use_argb = 1;
+        picture.width = width as i32;
+        picture.height = height as i32;
+
+        let added = unsafe {
+            let imported =
+                WebPPictureImportRGBA(&mut picture, frame.buffer().as_ptr(), width as i32 * 4) != 0;
+            let added = imported
+                && WebPAnimEncoderAdd(encoder.0, &mut picture, timestamp as i32, &config) != 0;
+            WebPPictureFree(&mut picture);
+            added
+        };
+        if !added {
+            return Err(encoder.error());
+        }
+
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        timestamp += (numer as f64 / denom as f64).round();
+    }
+
+    unsafe {
+        let mut data = WebPData::default();
+        if WebPAnimEncoderAdd(encoder.0, ptr::null_mut(), timestamp as i32, ptr::null()) == 0
+            || WebPAnimEncoderAssemble(encoder.0, &mut data) == 0
+        {
+            return Err(encoder.error());
+        }
+
+        let bytes = slice::from_raw_parts(data.bytes, data.size).to_vec();
+        WebPDataClear(&mut data);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{animation::decode_animation_with_limits, utils::DecodeLimits};
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    #[test]
+    fn keeps_every_frame_and_the_last_delay() {
+        let frame = |shade, delay_ms| {
+            let img = RgbaImage::from_pixel(16, 8, Rgba([shade, 0, 255 - shade, 255]));
+            Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))
+        };
+        let animation = Animation {
+            frames: vec![frame(0, 100), frame(255, 250)],
+            plays: 3,
+        };
+
+        for quality in [None, Some(90)] {
+            let webp = encode(&animation, quality).unwrap();
+            let decoded =
+                decode_animation_with_limits(webp.into(), DecodeLimits::from_config()).unwrap();
+
+            let delays: Vec<_> = decoded
+                .frames
+                .iter()
+                .map(|frame| frame.delay().numer_denom_ms())
+                .collect();
+            assert_eq!(delays, [(100, 1), (250, 1)]);
+            assert_eq!(decoded.plays, 3);
+            assert_eq!(decoded.dimensions(), (16, 8));
+
+            // lossy frames only keep the rough color
+            let [red, _, blue, alpha] = decoded.frames[1].buffer()[(0, 0)].0;
+            match quality {
+                None => assert_eq!([red, blue, alpha], [255, 0, 255]),
+                Some(_) => assert!(red > blue && alpha == 255),
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod utility;
 // home
 
+use crate::state::AppState;
 use axum::Router;
 // Image
 pub use image::generate_captcha_image;
@@ -40,7 +41,7 @@ use utility::UtilityDocs;
 )]
 pub struct ApiDocs;
 
-pub fn router() -> Router {
+pub fn router() -> Router<AppState> {
     Router::new()
         .nest("/utility", utility::router())
         .nest("/image", image::router())
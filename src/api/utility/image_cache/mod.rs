@@ -0,0 +1,20 @@
+use crate::{fetch::CacheStats, state::AppState};
+use axum::{extract::State, response::Json};
+
+#[utoipa::path(get, path = "/image_cache", responses(
+    (
+        status = 200,
+        body = inline(CacheStats),
+        description = "The hit/miss counters and size of the remote image cache",
+        example = json!({
+            "hits": 42,
+            "misses": 7,
+            "revalidations": 3,
+            "entries": 5,
+            "bytes": 1048576
+        })
+    )
+))]
+pub async fn image_cache_stats(State(state): State<AppState>) -> Json<CacheStats> {
+    Json(state.image_cache.stats())
+}
@@ -1,22 +1,36 @@
-use axum::{routing::get, Router};
+use crate::state::AppState;
+use axum::{
+    routing::{get, post},
+    Router,
+};
+pub mod image_cache;
 pub mod random_color;
 use super::image::captcha;
 
 mod docs {
-    use super::random_color::*;
+    use super::{image_cache::*, random_color::*};
     use crate::api::image::captcha::*;
     use utoipa::OpenApi;
 
     #[derive(OpenApi)]
-    #[openapi(paths(random_color, generate_captcha_response))]
+    #[openapi(paths(
+        random_color,
+        generate_captcha_response,
+        verify_captcha,
+        captcha_challenge_audio,
+        image_cache_stats
+    ))]
     pub struct UtilityDocs;
 }
 
 pub use docs::UtilityDocs;
 
 // Starts with `/utility/{...}`
-pub fn router() -> Router {
+pub fn router() -> Router<AppState> {
     Router::new()
         .route("/randomcolor", get(random_color::random_color))
         .route("/captcha", get(captcha::generate_captcha_response))
+        .route("/captcha/verify", post(captcha::verify_captcha))
+        .route("/captcha/:id/audio", get(captcha::captcha_challenge_audio))
+        .route("/image_cache", get(image_cache::image_cache_stats))
 }
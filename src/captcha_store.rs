@@ -0,0 +1,200 @@
+use crate::error::ApiError;
+use image::RgbImage;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use utoipa::ToSchema;
+
+/// A captcha that was handed out and hasn't been answered yet.
+#[derive(Debug, Clone)]
+pub struct Challenge {
+    /// What the captcha shows, e.g. `7 + 4`
+    pub text: String,
+    /// What has to be answered, e.g. `11`
+    pub answer: String,
+    /// The image is drawn once, so fetching it again doesn't help with averaging out the noise
+    pub image: Arc<RgbImage>,
+    pub case_sensitive: bool,
+    /// How noisy the audio version is, from `0.0` to `1.0`
+    pub audio_noise: f32,
+    /// Seeds the noise of the audio version
+    pub audio_seed: u64,
+}
+
+impl Challenge {
+    fn accepts(&self, answer: &str) -> bool {
+        let answer = answer.trim();
+
+        if self.case_sensitive {
+            answer == self.answer
+        } else {
+            answer.to_lowercase() == self.answer.to_lowercase()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Verification {
+    Success,
+    /// The answer was wrong, the challenge can't be answered again
+    Failure,
+    /// The challenge expired, was already answered or never existed
+    Expired,
+}
+
+struct Pending {
+    challenge: Challenge,
+    expires_at: Instant,
+}
+
+impl Pending {
+    fn is_expired(&self) -> bool {
+        Instant::now() >= self.expires_at
+    }
+}
+
+/// The pending captcha challenges, kept server side so their solutions are never handed out.
+pub struct CaptchaStore {
+    challenges: Mutex<HashMap<String, Pending>>,
+    ttl: Duration,
+    max_pending: usize,
+    max_pending_bytes: usize,
+}
+
+impl CaptchaStore {
+    /// The length of the random ids, 32 alphanumeric characters are about 190 bits.
+    const ID_LENGTH: usize = 32;
+
+    pub fn new(ttl: Duration, max_pending: usize, max_pending_bytes: usize) -> Self {
+        Self {
+            challenges: Mutex::new(HashMap::new()),
+            ttl,
+            max_pending,
+            max_pending_bytes,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Stores `challenge` until it expires, returning its id.
+    ///
+    /// Expired challenges are dropped first. If the store is still full, by count or by the size of the images, this
+    /// fails with [`ApiError::Busy`] instead of evicting challenges that can still be answered.
+    pub fn insert(&self, challenge: Challenge) -> Result<String, ApiError> {
+        let mut challenges = self.challenges.lock().unwrap();
+
+        challenges.retain(|_, pending| !pending.is_expired());
+        let bytes: usize = challenges
+            .values()
+            .map(|pending| pending.challenge.image.len())
+            .sum();
+        if challenges.len() >= self.max_pending
+            || bytes + challenge.image.len() > self.max_pending_bytes
+        {
+            return Err(ApiError::Busy);
+        }
+
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(Self::ID_LENGTH)
+            .map(char::from)
+            .collect();
+
+        challenges.insert(
+            id.clone(),
+            Pending {
+                challenge,
+                expires_at: Instant::now() + self.ttl,
+            },
+        );
+
+        Ok(id)
+    }
+
+    /// Looks up a challenge without using it up, e.g. to render it.
+    pub fn get(&self, id: &str) -> Option<Challenge> {
+        let challenges = self.challenges.lock().unwrap();
+        challenges
+            .get(id)
+            .filter(|pending| !pending.is_expired())
+            .map(|pending| pending.challenge.clone())
+    }
+
+    /// Checks `answer` and removes the challenge, so every challenge can only be answered once.
+    pub fn verify(&self, id: &str, answer: &str) -> Verification {
+        let pending = self.challenges.lock().unwrap().remove(id);
+
+        match pending {
+            Some(pending) if pending.is_expired() => Verification::Expired,
+            Some(pending) if pending.challenge.accepts(answer) => Verification::Success,
+            Some(_) => Verification::Failure,
+            None => Verification::Expired,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(answer: &str) -> Challenge {
+        Challenge {
+            text: answer.to_owned(),
+            answer: answer.to_owned(),
+            image: Arc::new(RgbImage::new(10, 10)),
+            case_sensitive: false,
+            audio_noise: 0.0,
+            audio_seed: 0,
+        }
+    }
+
+    #[test]
+    fn rejects_new_challenges_when_full() {
+        let store = CaptchaStore::new(Duration::from_secs(60), 2, usize::MAX);
+        let first = store.insert(challenge("a")).unwrap();
+        let second = store.insert(challenge("b")).unwrap();
+
+        assert!(matches!(store.insert(challenge("c")), Err(ApiError::Busy)));
+        // the pending challenges can still be answered
+        assert_eq!(store.verify(&first, "A"), Verification::Success);
+        assert_eq!(store.verify(&second, "x"), Verification::Failure);
+        assert_eq!(store.verify(&second, "b"), Verification::Expired);
+
+        // answered challenges make room again
+        assert!(store.insert(challenge("c")).is_ok());
+    }
+
+    #[test]
+    fn drops_expired_challenges_on_every_insert() {
+        let store = CaptchaStore::new(Duration::ZERO, 10, usize::MAX);
+        let expired = store.insert(challenge("a")).unwrap();
+        let fresh = store.insert(challenge("b")).unwrap();
+
+        assert_ne!(expired, fresh);
+        assert_eq!(store.challenges.lock().unwrap().len(), 1);
+
+        // a full store of expired challenges still accepts new ones
+        let store = CaptchaStore::new(Duration::ZERO, 1, usize::MAX);
+        store.insert(challenge("a")).unwrap();
+        assert!(store.insert(challenge("b")).is_ok());
+    }
+
+    #[test]
+    fn rejects_new_challenges_beyond_the_image_bytes() {
+        // every test image has 300 bytes
+        let store = CaptchaStore::new(Duration::from_secs(60), 10, 700);
+        let first = store.insert(challenge("a")).unwrap();
+        store.insert(challenge("b")).unwrap();
+
+        assert!(matches!(store.insert(challenge("c")), Err(ApiError::Busy)));
+        store.verify(&first, "a");
+        assert!(store.insert(challenge("c")).is_ok());
+    }
+}
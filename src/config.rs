@@ -0,0 +1,108 @@
+use lazy_static::lazy_static;
+use std::{env, fmt::Debug, path::PathBuf, str::FromStr, thread, time::Duration};
+
+lazy_static! {
+    /// The configuration of the API, read once from the environment.
+    pub static ref CONFIG: Config = Config::from_env();
+}
+
+#[derive(Debug)]
+pub struct Config {
+    /// The maximum size of an image in bytes, whether it's fetched or uploaded (`MAX_IMAGE_BYTES`).
+    pub max_image_bytes: usize,
+    /// The maximum width of a decoded image (`IMAGE_MAX_WIDTH`).
+    pub image_max_width: u32,
+    /// The maximum height of a decoded image (`IMAGE_MAX_HEIGHT`).
+    pub image_max_height: u32,
+    /// The maximum number of pixels of a decoded image (`IMAGE_MAX_PIXELS`).
+    pub image_max_pixels: u64,
+    /// The maximum number of bytes a decoder may allocate (`IMAGE_MAX_ALLOC_BYTES`).
+    pub image_max_alloc_bytes: u64,
+    /// The maximum number of frames of an animated image (`IMAGE_MAX_FRAMES`).
+    pub image_max_frames: u32,
+    /// The maximum number of pixels of all frames of an animated image together (`IMAGE_MAX_ANIMATION_PIXELS`).
+    pub image_max_animation_pixels: u64,
+    /// The URL schemes remote images may be fetched from (`FETCH_ALLOWED_SCHEMES`).
+    pub fetch_allowed_schemes: Vec<String>,
+    /// If not empty, only these hosts may be fetched from (`FETCH_ALLOWED_HOSTS`).
+    pub fetch_allowed_hosts: Vec<String>,
+    /// Hosts that may never be fetched from (`FETCH_DENIED_HOSTS`).
+    pub fetch_denied_hosts: Vec<String>,
+    /// The maximum total size of cached remote images in bytes, `0` disables the cache (`IMAGE_CACHE_MAX_BYTES`).
+    pub image_cache_max_bytes: usize,
+    /// How long remote images without `Cache-Control` freshness are cached, in seconds (`IMAGE_CACHE_DEFAULT_TTL`).
+    pub image_cache_default_ttl: Duration,
+    /// If set, cached remote images are stored in this directory instead of in memory (`IMAGE_CACHE_DIR`).
+    pub image_cache_dir: Option<PathBuf>,
+    /// The maximum width and height of a color preview (`PREVIEW_MAX_SIZE`).
+    pub preview_max_size: u32,
+    /// How long a captcha challenge can be answered, in seconds (`CAPTCHA_TTL`).
+    pub captcha_ttl: Duration,
+    /// The maximum number of unanswered captcha challenges, new ones are rejected beyond it (`CAPTCHA_MAX_PENDING`).
+    pub captcha_max_pending: usize,
+    /// The maximum total size of the images of unanswered captchas in bytes (`CAPTCHA_MAX_PENDING_BYTES`).
+    pub captcha_max_pending_bytes: usize,
+    /// Whether captcha answers are case-sensitive (`CAPTCHA_CASE_SENSITIVE`).
+    pub captcha_case_sensitive: bool,
+    /// How many CPU heavy tasks (decoding, encoding, ...) may run at once (`WORKER_CONCURRENCY`).
+    pub worker_concurrency: usize,
+    /// How long a task may wait for a free worker before the request fails with 503, in milliseconds (`WORKER_QUEUE_TIMEOUT_MS`).
+    pub worker_queue_timeout: Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Self {
+            max_image_bytes: env_parse("MAX_IMAGE_BYTES", 3 * 1024 * 1024),
+            image_max_width: env_parse("IMAGE_MAX_WIDTH", 8192),
+            image_max_height: env_parse("IMAGE_MAX_HEIGHT", 8192),
+            image_max_pixels: env_parse("IMAGE_MAX_PIXELS", 24_000_000),
+            image_max_alloc_bytes: env_parse("IMAGE_MAX_ALLOC_BYTES", 256 * 1024 * 1024),
+            image_max_frames: env_parse("IMAGE_MAX_FRAMES", 256),
+            image_max_animation_pixels: env_parse("IMAGE_MAX_ANIMATION_PIXELS", 64_000_000),
+            fetch_allowed_schemes: env_list("FETCH_ALLOWED_SCHEMES", &["http", "https"]),
+            fetch_allowed_hosts: env_list("FETCH_ALLOWED_HOSTS", &[]),
+            fetch_denied_hosts: env_list("FETCH_DENIED_HOSTS", &[]),
+            image_cache_max_bytes: env_parse("IMAGE_CACHE_MAX_BYTES", 64 * 1024 * 1024),
+            image_cache_default_ttl: Duration::from_secs(env_parse("IMAGE_CACHE_DEFAULT_TTL", 300)),
+            image_cache_dir: env::var_os("IMAGE_CACHE_DIR").map(PathBuf::from),
+            preview_max_size: env_parse("PREVIEW_MAX_SIZE", 2048),
+            captcha_ttl: Duration::from_secs(env_parse("CAPTCHA_TTL", 300)),
+            captcha_max_pending: env_parse("CAPTCHA_MAX_PENDING", 10_000),
+            captcha_max_pending_bytes: env_parse("CAPTCHA_MAX_PENDING_BYTES", 256 * 1024 * 1024),
+            captcha_case_sensitive: env_parse("CAPTCHA_CASE_SENSITIVE", false),
+            worker_concurrency: env_parse(
+                "WORKER_CONCURRENCY",
+                thread::available_parallelism().map_or(4, usize::from),
+            ),
+            worker_queue_timeout: Duration::from_millis(env_parse("WORKER_QUEUE_TIMEOUT_MS", 5000)),
+        }
+    }
+}
+
+/// Reads a comma separated, case-insensitive list from the environment.
+fn env_list(key: &str, default: &[&str]) -> Vec<String> {
+    match env::var(key) {
+        Ok(value) => value
+            .split(',')
+            .map(|entry| entry.trim().to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect(),
+        Err(_) => default.iter().map(|entry| entry.to_string()).collect(),
+    }
+}
+
+/// Reads a value from the environment, panicking on values that can't be parsed so misconfigurations surface on startup.
+fn env_parse<T>(key: &str, default: T) -> T
+where
+    T: FromStr,
+    T::Err: Debug,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .unwrap_or_else(|err| panic!("invalid value for `{key}`: {err:?}")),
+        Err(_) => default,
+    }
+}
@@ -1,5 +1,9 @@
+use crate::fetch::FetchPolicyError;
 use axum::{
-    extract::rejection::{JsonRejection, QueryRejection},
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{BytesRejection, JsonRejection, PathRejection, QueryRejection},
+    },
     http::StatusCode,
     response::IntoResponse,
 };
@@ -28,11 +32,31 @@ impl ErrorResponse {
 pub enum ApiError {
     QueryRejection(#[from] QueryRejection),
     JsonRejection(#[from] JsonRejection),
+    PathRejection(#[from] PathRejection),
+    BytesRejection(#[from] BytesRejection),
+    MultipartRejection(#[from] MultipartRejection),
+    Multipart(#[from] MultipartError),
     ImageError(#[from] ImageError),
     Io(#[from] io::Error),
     Reqwest(#[from] reqwest::Error),
     #[error("{0}")]
     FetchError(String),
+    FetchPolicy(#[from] FetchPolicyError),
+    #[error("The requested URL responded with `{0}`.")]
+    Upstream(StatusCode),
+    #[error("{0}")]
+    UnsupportedImage(String),
+    #[error("{0}")]
+    ImageTooLarge(String),
+    #[error("The image looks like {format} but couldn't be decoded: {source}")]
+    Decode {
+        format: &'static str,
+        source: ImageError,
+    },
+    #[error("The server is busy, try again later.")]
+    Busy,
+    #[error("{0}")]
+    InvalidColor(String),
     #[error("{1}")]
     Any(StatusCode, String),
     #[error("{1}")]
@@ -50,10 +74,21 @@ impl IntoResponse for ApiError {
         let (code, msg) = match self {
             QueryRejection(err) => (StatusCode::BAD_REQUEST, err.body_text()),
             JsonRejection(err) => (StatusCode::BAD_REQUEST, err.body_text()),
+            PathRejection(err) => (err.status(), err.body_text()),
+            BytesRejection(err) => (err.status(), err.body_text()),
+            MultipartRejection(err) => (err.status(), err.body_text()),
+            Multipart(err) => (err.status(), err.body_text()),
             ImageError(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
             Io(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.kind().to_string()),
             Reqwest(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
             FetchError(msg) => (StatusCode::BAD_REQUEST, msg),
+            FetchPolicy(err) => (err.status(), err.to_string()),
+            err @ Upstream(_) => (StatusCode::BAD_GATEWAY, err.to_string()),
+            err @ UnsupportedImage(_) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, err.to_string()),
+            err @ ImageTooLarge(_) => (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()),
+            err @ Decode { .. } => (StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+            err @ Busy => (StatusCode::SERVICE_UNAVAILABLE, err.to_string()),
+            err @ InvalidColor(_) => (StatusCode::BAD_REQUEST, err.to_string()),
             Any(code, msg) => (code, msg),
             AnyStatic(code, msg) => (code, msg.to_owned()),
         };
@@ -1,6 +1,9 @@
-use crate::error::ApiError;
+use crate::{config::CONFIG, error::ApiError, utils::format_bytes};
 use axum::{
-    extract::{FromRequest, FromRequestParts},
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, FromRequestParts, Multipart, Request},
+    http::{header, StatusCode},
     response::IntoResponse,
 };
 use serde::Serialize;
@@ -19,3 +22,74 @@ impl<T: Serialize> IntoResponse for Json<T> {
 #[derive(Debug, FromRequestParts)]
 #[from_request(via(axum::extract::Query), rejection(ApiError))]
 pub struct Query<T>(pub T);
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+/// The raw bytes of an uploaded image.
+///
+/// Accepts either a `multipart/form-data` body with an `image` field or a raw `image/*` body.
+#[derive(Debug)]
+pub struct ImageUpload(pub Bytes);
+
+impl ImageUpload {
+    /// The name of the multipart field that holds the image.
+    pub const FIELD_NAME: &'static str = "image";
+}
+
+#[async_trait]
+impl<S> FromRequest<S> for ImageUpload
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        let bytes = if content_type.starts_with("multipart/form-data") {
+            let mut multipart = Multipart::from_request(req, state).await?;
+
+            loop {
+                let Some(field) = multipart.next_field().await? else {
+                    return Err(ApiError::Any(
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "The multipart body has no `{}` field.",
+                            ImageUpload::FIELD_NAME
+                        ),
+                    ));
+                };
+
+                if field.name() == Some(ImageUpload::FIELD_NAME) {
+                    break field.bytes().await?;
+                }
+            }
+        } else if content_type.starts_with("image/") {
+            Bytes::from_request(req, state).await?
+        } else {
+            return Err(ApiError::AnyStatic(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Expected a `multipart/form-data` or `image/*` body.",
+            ));
+        };
+
+        if bytes.len() > CONFIG.max_image_bytes {
+            return Err(ApiError::Any(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "The uploaded image cannot exceed {}.",
+                    format_bytes(CONFIG.max_image_bytes)
+                ),
+            ));
+        }
+
+        Ok(Self(bytes))
+    }
+}
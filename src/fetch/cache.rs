@@ -0,0 +1,404 @@
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap},
+};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use utoipa::ToSchema;
+
+/// The validators and freshness of a cached response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheMeta {
+    url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Seconds since the unix epoch after which the entry has to be revalidated.
+    expires_at: u64,
+}
+
+impl CacheMeta {
+    /// Builds the metadata of a response, or `None` if it may not be stored.
+    pub fn from_headers(url: &str, headers: &HeaderMap, default_ttl: Duration) -> Option<Self> {
+        let ttl = freshness(headers, default_ttl)?;
+        let header_str = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+
+        Some(Self {
+            url: url.to_owned(),
+            etag: header_str(header::ETAG),
+            last_modified: header_str(header::LAST_MODIFIED),
+            expires_at: unix_now() + ttl.as_secs(),
+        })
+    }
+
+    /// Updates the metadata with the headers of a `304 Not Modified` response.
+    pub fn revalidated(mut self, headers: &HeaderMap, default_ttl: Duration) -> Self {
+        if let Some(refreshed) = Self::from_headers(&self.url, headers, default_ttl) {
+            self.expires_at = refreshed.expires_at;
+            self.etag = refreshed.etag.or(self.etag);
+            self.last_modified = refreshed.last_modified.or(self.last_modified);
+        } else {
+            self.expires_at = unix_now();
+        }
+        self
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        unix_now() < self.expires_at
+    }
+}
+
+/// How long a response may be used without revalidation, or `None` if it may not be stored at all.
+///
+/// `s-maxage` and `max-age` are honoured, `no-cache` forces a revalidation on every use and responses without
+/// either are considered fresh for `default_ttl`.
+fn freshness(headers: &HeaderMap, default_ttl: Duration) -> Option<Duration> {
+    let directives: Vec<String> = headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .collect();
+
+    let max_age = |name: &str| {
+        directives
+            .iter()
+            .filter_map(|directive| directive.strip_prefix(name)?.strip_prefix('='))
+            .find_map(|secs| secs.trim_matches('"').parse().ok())
+            .map(Duration::from_secs)
+    };
+
+    if directives
+        .iter()
+        .any(|directive| directive == "no-store" || directive == "private")
+    {
+        None
+    } else if directives.iter().any(|directive| directive == "no-cache") {
+        Some(Duration::ZERO)
+    } else {
+        max_age("s-maxage")
+            .or_else(|| max_age("max-age"))
+            .or(Some(default_ttl))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub revalidations: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+struct Entry {
+    meta: CacheMeta,
+    size: usize,
+    /// The body, unless it's stored on disk.
+    body: Option<Bytes>,
+}
+
+struct Entries {
+    lru: LruCache<String, Entry>,
+    bytes: usize,
+}
+
+/// An LRU cache for remote images, bounded by the total size of the cached bodies.
+///
+/// Bodies are kept in memory, or on disk if a directory is given.
+pub struct ImageCache {
+    entries: Mutex<Entries>,
+    max_bytes: usize,
+    default_ttl: Duration,
+    dir: Option<PathBuf>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    revalidations: AtomicU64,
+}
+
+impl ImageCache {
+    pub fn new(max_bytes: usize, default_ttl: Duration, dir: Option<PathBuf>) -> Self {
+        let cache = Self {
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            max_bytes,
+            default_ttl,
+            dir,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            revalidations: AtomicU64::new(0),
+        };
+
+        if let Some(dir) = &cache.dir {
+            if let Err(err) = cache.load_dir(dir) {
+                eprintln!(
+                    "couldn't load the image cache from {}: {err}",
+                    dir.display()
+                );
+            }
+        }
+
+        cache
+    }
+
+    pub fn default_ttl(&self) -> Duration {
+        self.default_ttl
+    }
+
+    /// Picks up the entries a previous run left on disk, evicting the least recently stored ones if they don't fit.
+    fn load_dir(&self, dir: &Path) -> std::io::Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let mut stored = Vec::new();
+        for file in fs::read_dir(dir)? {
+            let path = file?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let meta = fs::read(&path)
+                    .ok()
+                    .and_then(|json| serde_json::from_slice::<CacheMeta>(&json).ok())
+                    // Files named differently than their URL would be stored are leftovers or were tampered with.
+                    .filter(|meta| file_path(dir, &meta.url, "json") == path);
+                let body = fs::metadata(path.with_extension("bin"));
+
+                match (meta, body) {
+                    (Some(meta), Ok(body)) => {
+                        let modified = body.modified().unwrap_or(UNIX_EPOCH);
+                        stored.push((modified, meta, body.len() as usize));
+                    }
+                    _ => {
+                        let _ = fs::remove_file(&path);
+                        let _ = fs::remove_file(path.with_extension("bin"));
+                    }
+                }
+            }
+        }
+
+        stored.sort_by_key(|(modified, ..)| *modified);
+        let evicted = {
+            let mut entries = self.entries.lock().unwrap();
+            for (_, meta, size) in stored {
+                entries.bytes += size;
+                entries.lru.put(
+                    meta.url.clone(),
+                    Entry {
+                        meta,
+                        size,
+                        body: None,
+                    },
+                );
+            }
+            self.evict(&mut entries)
+        };
+
+        for url in evicted {
+            let _ = fs::remove_file(file_path(dir, &url, "bin"));
+            let _ = fs::remove_file(file_path(dir, &url, "json"));
+        }
+
+        Ok(())
+    }
+
+    /// Looks up `url`, whether it's fresh or not.
+    pub async fn get(&self, url: &str) -> Option<(CacheMeta, Bytes)> {
+        let (meta, body) = {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.lru.get(url)?;
+            (entry.meta.clone(), entry.body.clone())
+        };
+
+        let body = match (body, &self.dir) {
+            (Some(body), _) => body,
+            (None, Some(dir)) => match tokio::fs::read(file_path(dir, url, "bin")).await {
+                Ok(body) => body.into(),
+                Err(_) => {
+                    self.remove(url).await;
+                    return None;
+                }
+            },
+            (None, None) => return None,
+        };
+
+        Some((meta, body))
+    }
+
+    pub async fn insert(&self, meta: CacheMeta, body: Bytes) {
+        if body.len() > self.max_bytes {
+            return;
+        }
+
+        let url = meta.url.clone();
+        let size = body.len();
+
+        let body = match &self.dir {
+            Some(dir) => {
+                let json = serde_json::to_vec(&meta).unwrap_or_default();
+                let stored = tokio::fs::write(file_path(dir, &url, "bin"), &body)
+                    .await
+                    .is_ok()
+                    && tokio::fs::write(file_path(dir, &url, "json"), json)
+                        .await
+                        .is_ok();
+                if !stored {
+                    return;
+                }
+                None
+            }
+            None => Some(body),
+        };
+
+        let evicted = {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(old) = entries.lru.pop(&url) {
+                entries.bytes -= old.size;
+            }
+            entries.bytes += size;
+            entries.lru.put(url.clone(), Entry { meta, size, body });
+            self.evict(&mut entries)
+        };
+
+        if let Some(dir) = &self.dir {
+            for url in evicted {
+                remove_files(dir, &url).await;
+            }
+        }
+    }
+
+    /// Drops the least recently used entries until the cache fits `max_bytes`, returning their URLs.
+    fn evict(&self, entries: &mut Entries) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while entries.bytes > self.max_bytes {
+            let Some((url, entry)) = entries.lru.pop_lru() else {
+                break;
+            };
+            entries.bytes -= entry.size;
+            evicted.push(url);
+        }
+        evicted
+    }
+
+    async fn remove(&self, url: &str) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            if let Some(old) = entries.lru.pop(url) {
+                entries.bytes -= old.size;
+            }
+        }
+
+        if let Some(dir) = &self.dir {
+            remove_files(dir, url).await;
+        }
+    }
+
+    pub fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_revalidation(&self) {
+        self.revalidations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            revalidations: self.revalidations.load(Ordering::Relaxed),
+            entries: entries.lru.len(),
+            bytes: entries.bytes,
+        }
+    }
+}
+
+async fn remove_files(dir: &Path, url: &str) {
+    let _ = tokio::fs::remove_file(file_path(dir, url, "bin")).await;
+    let _ = tokio::fs::remove_file(file_path(dir, url, "json")).await;
+}
+
+/// The file an entry is stored in, named after the SHA-256 hash of its URL so no two URLs share a file.
+fn file_path(dir: &Path, url: &str, extension: &str) -> PathBuf {
+    let hash: String = Sha256::digest(url.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    dir.join(format!("{hash}.{extension}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(url: &str) -> CacheMeta {
+        CacheMeta::from_headers(url, &HeaderMap::new(), Duration::from_secs(60)).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("image-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn stores_every_url_in_its_own_file() {
+        let dir = temp_dir("files");
+        let cache = ImageCache::new(1024, Duration::from_secs(60), Some(dir.clone()));
+
+        cache
+            .insert(meta("https://a.test/"), Bytes::from_static(b"a"))
+            .await;
+        cache
+            .insert(meta("https://b.test/"), Bytes::from_static(b"b"))
+            .await;
+
+        assert_ne!(
+            file_path(&dir, "https://a.test/", "bin"),
+            file_path(&dir, "https://b.test/", "bin")
+        );
+        assert_eq!(cache.get("https://a.test/").await.unwrap().1, "a");
+        assert_eq!(cache.get("https://b.test/").await.unwrap().1, "b");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn evicts_entries_that_dont_fit_on_startup() {
+        let dir = temp_dir("startup");
+        let cache = ImageCache::new(1024, Duration::from_secs(60), Some(dir.clone()));
+        for index in 0..4 {
+            let url = format!("https://{index}.test/");
+            cache.insert(meta(&url), vec![0; 256].into()).await;
+        }
+
+        let cache = ImageCache::new(512, Duration::from_secs(60), Some(dir.clone()));
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (2, 512));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
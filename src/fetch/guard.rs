@@ -0,0 +1,469 @@
+use crate::config::CONFIG;
+use axum::http::StatusCode;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect::{Attempt, Policy},
+    Url,
+};
+use std::{
+    error::Error as StdError,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use thiserror::Error;
+
+const MAX_REDIRECTS: usize = 5;
+
+/// Why a remote image may not be fetched.
+#[derive(Debug, Clone, Error)]
+pub enum FetchPolicyError {
+    #[error("`{0}` is not a valid URL.")]
+    InvalidUrl(String),
+    #[error("Fetching images via `{0}` is not allowed.")]
+    Scheme(String),
+    #[error("Fetching images from `{0}` is not allowed.")]
+    Host(String),
+    #[error("`{0}` does not resolve to a public address.")]
+    Address(String),
+}
+
+impl FetchPolicyError {
+    pub fn status(&self) -> StatusCode {
+        use FetchPolicyError::*;
+
+        match self {
+            InvalidUrl(_) | Scheme(_) => StatusCode::BAD_REQUEST,
+            Host(_) | Address(_) => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+/// Parses `url` and checks it against [`check_url`].
+pub fn parse_url(url: &str) -> Result<Url, FetchPolicyError> {
+    let url = Url::parse(url).map_err(|_| FetchPolicyError::InvalidUrl(url.to_owned()))?;
+    check_url(&url)?;
+    Ok(url)
+}
+
+/// Checks `url` against the configured [`FetchPolicy`].
+pub fn check_url(url: &Url) -> Result<(), FetchPolicyError> {
+    FetchPolicy::from_config().check(url)
+}
+
+/// The schemes and hosts remote images may be fetched from.
+#[derive(Debug, Clone, Copy)]
+pub struct FetchPolicy<'a> {
+    pub allowed_schemes: &'a [String],
+    /// Every host is allowed if this is empty.
+    pub allowed_hosts: &'a [String],
+    pub denied_hosts: &'a [String],
+}
+
+impl FetchPolicy<'static> {
+    pub fn from_config() -> Self {
+        Self {
+            allowed_schemes: &CONFIG.fetch_allowed_schemes,
+            allowed_hosts: &CONFIG.fetch_allowed_hosts,
+            denied_hosts: &CONFIG.fetch_denied_hosts,
+        }
+    }
+}
+
+impl FetchPolicy<'_> {
+    /// Checks the scheme and host of `url` against the allow and deny lists.
+    ///
+    /// Hosts that are IP literals have to be public, domains are checked by [`PublicResolver`] once they're resolved.
+    pub fn check(&self, url: &Url) -> Result<(), FetchPolicyError> {
+        let scheme = url.scheme();
+        if !self.allowed_schemes.iter().any(|s| s == scheme) {
+            return Err(FetchPolicyError::Scheme(scheme.to_owned()));
+        }
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| FetchPolicyError::InvalidUrl(url.to_string()))?
+            .to_ascii_lowercase();
+
+        let denied = self
+            .denied_hosts
+            .iter()
+            .any(|pattern| host_matches(pattern, &host));
+        let allowed = self.allowed_hosts.is_empty()
+            || self
+                .allowed_hosts
+                .iter()
+                .any(|pattern| host_matches(pattern, &host));
+
+        if denied || !allowed {
+            return Err(FetchPolicyError::Host(host));
+        }
+
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = literal.parse::<IpAddr>() {
+            if !is_public(ip) {
+                return Err(FetchPolicyError::Address(host));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Matches `host` against `pattern`, which is either an exact host or `*.example.com` for any subdomain.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_multicast()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        // "this" network
+        || a == 0
+        // shared address space
+        || (a == 100 && (b & 0xc0) == 64)
+        // benchmarking
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = embedded_v4(ip) {
+        return !ip.is_unspecified() && !ip.is_loopback() && is_public_v4(v4);
+    }
+
+    let segments = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local
+        || (segments[0] & 0xfe00) == 0xfc00
+        // link local
+        || (segments[0] & 0xffc0) == 0xfe80
+        // documentation
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+/// The IPv4 address an IPv6 address ends up at, if it carries one.
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let v4 = |hi: u16, lo: u16| Ipv4Addr::from((hi as u32) << 16 | lo as u32);
+
+    match segments {
+        // IPv4-compatible
+        [0, 0, 0, 0, 0, 0, hi, lo] => Some(v4(hi, lo)),
+        // IPv4-mapped
+        [0, 0, 0, 0, 0, 0xffff, hi, lo] => Some(v4(hi, lo)),
+        // NAT64
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some(v4(hi, lo)),
+        // 6to4 tunnels to the address right after the prefix
+        [0x2002, hi, lo, ..] => Some(v4(hi, lo)),
+        // Teredo stores the client's address inverted at the end
+        [0x2001, 0, .., hi, lo] => Some(v4(!hi, !lo)),
+        _ => None,
+    }
+}
+
+/// A DNS resolver that refuses to resolve hosts to anything but public addresses.
+///
+/// Since the addresses are checked when connecting, this also covers redirects and DNS rebinding.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0)).await?.collect();
+
+            if addrs.iter().any(|addr| !is_public(addr.ip())) {
+                return Err(Box::new(FetchPolicyError::Address(host.to_owned())) as _);
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Follows up to [`MAX_REDIRECTS`] redirects, as long as every hop passes [`check_url`].
+pub fn redirect_policy() -> Policy {
+    Policy::custom(|attempt: Attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(err) => attempt.error(err),
+        }
+    })
+}
+
+/// Finds the [`FetchPolicyError`] that caused `err`, if there is one.
+pub fn policy_violation(err: &reqwest::Error) -> Option<FetchPolicyError> {
+    let mut source = err.source();
+
+    while let Some(err) = source {
+        if let Some(violation) = err.downcast_ref::<FetchPolicyError>() {
+            return Some(violation.clone());
+        }
+        source = err.source();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn check(policy: FetchPolicy, url: &str) -> Result<(), FetchPolicyError> {
+        policy.check(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn rejects_non_public_ipv4_addresses() {
+        let private = [
+            "0.0.0.0",
+            "0.1.2.3",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.0.1",
+            // cloud metadata services
+            "169.254.169.254",
+            // CGNAT
+            "100.64.0.1",
+            "100.127.255.255",
+            "198.18.0.1",
+            "192.0.2.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ];
+        for ip in private {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in ["1.1.1.1", "93.184.216.34", "100.63.255.255", "100.128.0.1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn rejects_non_public_ipv6_addresses() {
+        let private = [
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+        ];
+        for ip in private {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        assert!(is_public("2606:4700:4700::1111".parse().unwrap()));
+    }
+
+    #[test]
+    fn checks_the_ipv4_address_inside_ipv6_addresses() {
+        let private = [
+            // mapped
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            // compatible
+            "::10.0.0.1",
+            // NAT64
+            "64:ff9b::192.168.0.1",
+            // 6to4 of 127.0.0.1 and 10.0.0.1
+            "2002:7f00:1::",
+            "2002:a00:1::1",
+            // Teredo with the client 127.0.0.1 and 169.254.169.254
+            "2001:0:4136:e378:8000:63bf:80ff:fffe",
+            "2001:0:4136:e378:8000:63bf:5601:5601",
+        ];
+        for ip in private {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        let public = [
+            "::ffff:1.1.1.1",
+            "64:ff9b::1.1.1.1",
+            "2002:101:101::",
+            // Teredo with the client 1.1.1.1
+            "2001:0:4136:e378:8000:63bf:fefe:fefe",
+        ];
+        for ip in public {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn matches_exact_hosts_and_subdomain_wildcards() {
+        assert!(host_matches("example.com", "example.com"));
+        assert!(!host_matches("example.com", "www.example.com"));
+
+        assert!(host_matches("*.example.com", "cdn.example.com"));
+        assert!(host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "badexample.com"));
+        assert!(!host_matches("*.example.com", "example.com.evil.org"));
+    }
+
+    #[test]
+    fn applies_the_allow_and_deny_lists() {
+        let (schemes, none) = (strings(&["http", "https"]), Vec::new());
+        let allowed = strings(&["*.example.com", "example.org"]);
+        let denied = strings(&["private.example.com"]);
+        let policy = FetchPolicy {
+            allowed_schemes: &schemes,
+            allowed_hosts: &allowed,
+            denied_hosts: &denied,
+        };
+
+        assert!(check(policy, "https://cdn.example.com/a.png").is_ok());
+        assert!(check(policy, "https://EXAMPLE.org/a.png").is_ok());
+        assert!(matches!(
+            check(policy, "https://private.example.com/a.png"),
+            Err(FetchPolicyError::Host(host)) if host == "private.example.com"
+        ));
+        assert!(matches!(
+            check(policy, "https://example.net/a.png"),
+            Err(FetchPolicyError::Host(_))
+        ));
+
+        let open = FetchPolicy {
+            allowed_hosts: &none,
+            ..policy
+        };
+        assert!(check(open, "https://example.net/a.png").is_ok());
+        assert!(check(open, "https://private.example.com/a.png").is_err());
+    }
+
+    #[test]
+    fn rejects_other_schemes() {
+        let (schemes, none) = (strings(&["http", "https"]), Vec::new());
+        let policy = FetchPolicy {
+            allowed_schemes: &schemes,
+            allowed_hosts: &none,
+            denied_hosts: &none,
+        };
+
+        for url in [
+            "file:///etc/passwd",
+            "ftp://example.com/a.png",
+            "gopher://example.com",
+        ] {
+            assert!(
+                matches!(check(policy, url), Err(FetchPolicyError::Scheme(_))),
+                "{url}"
+            );
+        }
+        assert_eq!(
+            FetchPolicyError::Scheme("file".to_owned()).status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn rejects_non_public_ip_literals() {
+        let (schemes, none) = (strings(&["http", "https"]), Vec::new());
+        let policy = FetchPolicy {
+            allowed_schemes: &schemes,
+            allowed_hosts: &none,
+            denied_hosts: &none,
+        };
+
+        for url in [
+            "http://127.0.0.1/a.png",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]:8080/a.png",
+            "http://[::ffff:10.0.0.1]/a.png",
+            "http://[2002:7f00:1::]/a.png",
+            // the URL parser normalizes other notations of IPv4 addresses
+            "http://2130706433/a.png",
+            "http://0x7f.1/a.png",
+        ] {
+            let result = check(policy, url);
+            assert!(
+                matches!(&result, Err(FetchPolicyError::Address(_))),
+                "{url}: {result:?}"
+            );
+        }
+        assert!(check(policy, "http://1.1.1.1/a.png").is_ok());
+        assert!(check(policy, "http://[2606:4700:4700::1111]/a.png").is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolves_only_to_public_addresses() {
+        let resolved = PublicResolver.resolve("localhost".parse().unwrap()).await;
+
+        let err = resolved.err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<FetchPolicyError>(),
+            Some(FetchPolicyError::Address(host)) if host == "localhost"
+        ));
+    }
+
+    #[tokio::test]
+    async fn refuses_redirects_to_non_public_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 302 Found\r\nLocation: http://127.0.0.1:{port}/secret\r\nContent-Length: 0\r\n\r\n"
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client = reqwest::Client::builder()
+            .no_proxy()
+            .redirect(redirect_policy())
+            .build()
+            .unwrap();
+        // The first request is sent directly, `parse_url` would have refused it already.
+        let err = client
+            .get(format!("http://127.0.0.1:{port}/image.png"))
+            .send()
+            .await
+            .unwrap_err();
+
+        assert!(err.is_redirect());
+        assert!(matches!(
+            policy_violation(&err),
+            Some(FetchPolicyError::Address(_))
+        ));
+    }
+}
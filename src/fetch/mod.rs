@@ -0,0 +1,149 @@
+mod cache;
+mod guard;
+
+use crate::{config::CONFIG, error::ApiError, state::AppState, utils::format_bytes};
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, StatusCode},
+};
+use cache::CacheMeta;
+pub use cache::{CacheStats, ImageCache};
+pub use guard::FetchPolicyError;
+use guard::{parse_url, policy_violation, redirect_policy, PublicResolver};
+use std::{sync::Arc, time::Duration};
+
+/// Builds the client used for fetching remote images, see [`guard`] for the restrictions it enforces.
+pub fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .no_proxy()
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirect_policy())
+        .timeout(Duration::from_secs(3))
+        .build()
+        .expect("the HTTP client should be buildable")
+}
+
+pub async fn fetch_raw_image(state: &AppState, url: &str) -> Result<Bytes, ApiError> {
+    let url = parse_url(url)?;
+    let cache = &state.image_cache;
+    let cached = cache.get(url.as_str()).await;
+
+    let mut request = state.http.get(url.clone());
+
+    if let Some((meta, body)) = &cached {
+        if meta.is_fresh() {
+            cache.record_hit();
+            return Ok(body.clone());
+        }
+
+        if let Some(etag) = &meta.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &meta.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+
+    let mut resp = request.send().await.map_err(request_error)?;
+
+    if let (StatusCode::NOT_MODIFIED, Some((meta, body))) = (resp.status(), cached) {
+        cache.record_revalidation();
+        let meta = meta.revalidated(resp.headers(), cache.default_ttl());
+        cache.insert(meta, body.clone()).await;
+        return Ok(body);
+    }
+
+    cache.record_miss();
+
+    if !resp.status().is_success() {
+        return Err(ApiError::Upstream(resp.status()));
+    }
+
+    check_content_type(resp.headers())?;
+
+    let limit = CONFIG.max_image_bytes;
+    let too_large = || {
+        ApiError::FetchError(format!(
+            "The requested content cannot exceed {}.",
+            format_bytes(limit)
+        ))
+    };
+
+    // the header is only a hint, the body itself is what's being limited
+    let content_length = resp.content_length().unwrap_or(0);
+    if content_length > limit as u64 {
+        return Err(too_large());
+    }
+
+    let mut body: Vec<u8> = Vec::with_capacity(content_length as usize);
+
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let body = Bytes::from(body);
+
+    if resp.status() == StatusCode::OK {
+        if let Some(meta) =
+            CacheMeta::from_headers(url.as_str(), resp.headers(), cache.default_ttl())
+        {
+            cache.insert(meta, body.clone()).await;
+        }
+    }
+
+    Ok(body)
+}
+
+/// The content types remote images may be served with. Generic binary types are accepted too, since the image
+/// format is detected by its magic bytes anyway.
+const ALLOWED_CONTENT_TYPES: &[&str] = &[
+    "image/png",
+    "image/apng",
+    "image/jpeg",
+    "image/jpg",
+    "image/pjpeg",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+    "image/x-bmp",
+    "image/tiff",
+    "image/x-icon",
+    "image/vnd.microsoft.icon",
+    "image/qoi",
+    "application/octet-stream",
+    "binary/octet-stream",
+];
+
+fn check_content_type(headers: &HeaderMap) -> Result<(), ApiError> {
+    let Some(content_type) = headers.get(header::CONTENT_TYPE) else {
+        return Ok(());
+    };
+
+    let mime = content_type
+        .to_str()
+        .unwrap_or_default()
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    if ALLOWED_CONTENT_TYPES.contains(&mime.as_str()) {
+        Ok(())
+    } else {
+        Err(ApiError::UnsupportedImage(format!(
+            "The requested URL responded with `{mime}`, which isn't a supported image type."
+        )))
+    }
+}
+
+/// Reports requests that were stopped by the fetch policy as such instead of as a generic request error.
+fn request_error(err: reqwest::Error) -> ApiError {
+    match policy_violation(&err) {
+        Some(violation) => violation.into(),
+        None => err.into(),
+    }
+}
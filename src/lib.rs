@@ -1,13 +1,20 @@
+pub mod animation;
 pub mod api;
+pub mod captcha_store;
+pub mod config;
 pub mod error;
 pub mod extract;
+pub mod fetch;
 mod home;
+pub mod state;
 pub mod utils;
+pub mod workers;
 
 use axum::{routing::get, Router};
 use error::ApiError;
 use extract::Json;
 use include_dir::{include_dir, Dir};
+use state::AppState;
 
 const TEMPLATES: Dir = include_dir!("./templates");
 
@@ -25,6 +32,7 @@ pub fn router() -> Router {
     Router::new()
         .merge(api::router())
         .route("/", get(home::home))
+        .with_state(AppState::new())
 }
 
 pub type ApiResult<T> = std::result::Result<Json<T>, ApiError>;
@@ -1,4 +1,4 @@
-use api_mettwasser_xyz::api::ApiDocs;
+use api_mettwasser_xyz::{api::ApiDocs, config::CONFIG};
 use axum::Router;
 use tower_http::services::ServeDir;
 use utoipa::OpenApi;
@@ -12,6 +12,8 @@ const HOST_IP: &str = "0.0.0.0:3000";
 
 #[tokio::main]
 async fn main() {
+    lazy_static::initialize(&CONFIG);
+
     let app = Router::new()
         .merge(api_mettwasser_xyz::router())
         .nest_service("/assets", ServeDir::new("assets"))
@@ -0,0 +1,45 @@
+use crate::{
+    captcha_store::CaptchaStore,
+    config::CONFIG,
+    fetch::{self, ImageCache},
+    workers::Workers,
+};
+use std::sync::Arc;
+
+/// The state shared by all handlers.
+#[derive(Clone)]
+pub struct AppState {
+    /// The client for all outgoing requests, so connections are pooled.
+    pub http: reqwest::Client,
+    pub image_cache: Arc<ImageCache>,
+    pub workers: Arc<Workers>,
+    pub captchas: Arc<CaptchaStore>,
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        Self {
+            http: fetch::client(),
+            image_cache: Arc::new(ImageCache::new(
+                CONFIG.image_cache_max_bytes,
+                CONFIG.image_cache_default_ttl,
+                CONFIG.image_cache_dir.clone(),
+            )),
+            workers: Arc::new(Workers::new(
+                CONFIG.worker_concurrency,
+                CONFIG.worker_queue_timeout,
+            )),
+            captchas: Arc::new(CaptchaStore::new(
+                CONFIG.captcha_ttl,
+                CONFIG.captcha_max_pending,
+                CONFIG.captcha_max_pending_bytes,
+            )),
+        }
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,38 +1,422 @@
-use crate::error::ApiError;
+use crate::{config::CONFIG, error::ApiError};
 use axum::body::Bytes;
-use image::io::Reader;
-use std::{io::Cursor, time::Duration};
-
-pub async fn fetch_raw_image(url: &str) -> Result<Bytes, ApiError> {
-    let resp = reqwest::Client::builder()
-        .build()?
-        .get(url)
-        .timeout(Duration::from_secs(3))
-        .send()
-        .await?;
-
-    if let Some(length) = resp.content_length() {
-        if length > 3 * 1024 * 1024 {
-            return Err(ApiError::FetchError(
-                "The requested content cannot exceed 3mb.".to_owned(),
-            ));
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    io::{Limits, Reader},
+    ImageError, ImageFormat,
+};
+use std::io::Cursor;
+
+/// The limits untrusted images are decoded with.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeLimits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    pub max_alloc: u64,
+    /// The most frames an animation may have.
+    pub max_frames: u32,
+    /// The most pixels all frames of an animation may have together.
+    pub max_animation_pixels: u64,
+}
+
+impl DecodeLimits {
+    pub fn from_config() -> Self {
+        Self {
+            max_width: CONFIG.image_max_width,
+            max_height: CONFIG.image_max_height,
+            max_pixels: CONFIG.image_max_pixels,
+            max_alloc: CONFIG.image_max_alloc_bytes,
+            max_frames: CONFIG.image_max_frames,
+            max_animation_pixels: CONFIG.image_max_animation_pixels,
         }
-    } else {
-        return Err(ApiError::FetchError(
-            "Couldn't retrieve the content length of the requested URL.".to_owned(),
-        ));
     }
 
-    Ok(resp.bytes().await?)
+    /// The limits passed on to the decoders of the `image` crate.
+    pub(crate) fn decoder_limits(&self) -> Limits {
+        let mut decoder_limits = Limits::default();
+        decoder_limits.max_image_width = Some(self.max_width);
+        decoder_limits.max_image_height = Some(self.max_height);
+        decoder_limits.max_alloc = Some(self.max_alloc);
+        decoder_limits
+    }
+
+    /// Checks the dimensions an image declares in its header, before anything is allocated for it.
+    pub fn check(&self, width: u32, height: u32) -> Result<(), ApiError> {
+        if width > self.max_width || height > self.max_height {
+            return Err(ApiError::ImageTooLarge(format!(
+                "The image is {width}x{height} pixels, but it cannot exceed {}x{}.",
+                self.max_width, self.max_height
+            )));
+        }
+
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_pixels {
+            return Err(ApiError::ImageTooLarge(format!(
+                "The image has {pixels} pixels, but it cannot exceed {}.",
+                self.max_pixels
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the number of frames of an animation and the pixels they have together.
+    pub fn check_animation(&self, frames: u64, frame_pixels: u64) -> Result<(), ApiError> {
+        if frames > self.max_frames as u64 {
+            return Err(ApiError::ImageTooLarge(format!(
+                "The animation has more than {} frames.",
+                self.max_frames
+            )));
+        }
+        if frames.saturating_mul(frame_pixels) > self.max_animation_pixels {
+            return Err(ApiError::ImageTooLarge(format!(
+                "The frames of the animation have more than {} pixels together.",
+                self.max_animation_pixels
+            )));
+        }
+
+        Ok(())
+    }
 }
 
+/// Decodes an image with the configured [`DecodeLimits`].
 pub fn image_from_bytes(
     bytes: Bytes,
 ) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, ApiError> {
-    let format = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
-    Ok(format.decode()?.to_rgba8())
+    decode_with_limits(bytes, DecodeLimits::from_config())
+}
+
+/// Decodes an image, detecting its format by its magic bytes rather than trusting any content type.
+pub fn decode_with_limits(
+    bytes: Bytes,
+    limits: DecodeLimits,
+) -> Result<image::ImageBuffer<image::Rgba<u8>, Vec<u8>>, ApiError> {
+    let (format, _) = inspect(&bytes, limits)?;
+
+    let mut reader = Reader::with_format(Cursor::new(&bytes), format);
+    reader.limits(limits.decoder_limits());
+
+    Ok(reader.decode().map_err(decode_error(format))?.to_rgba8())
+}
+
+/// Detects the format of an image and checks the dimensions it declares against `limits`.
+pub(crate) fn inspect(
+    bytes: &Bytes,
+    limits: DecodeLimits,
+) -> Result<(ImageFormat, (u32, u32)), ApiError> {
+    let format = image::guess_format(bytes).map_err(|_| {
+        ApiError::UnsupportedImage("The content isn't an image in a supported format.".to_owned())
+    })?;
+
+    let (width, height, frames) = match format {
+        ImageFormat::WebP => webp_header(bytes).ok_or_else(|| {
+            decode_error(format)(ImageError::Decoding(DecodingError::new(
+                ImageFormatHint::Exact(format),
+                "The WebP header is invalid.",
+            )))
+        })?,
+        _ => {
+            let (width, height) = Reader::with_format(Cursor::new(bytes), format)
+                .into_dimensions()
+                .map_err(decode_error(format))?;
+            (width, height, 0)
+        }
+    };
+    limits.check(width, height)?;
+    limits.check_animation(frames, width as u64 * height as u64)?;
+
+    Ok((format, (width, height)))
+}
+
+/// Reads the dimensions of a WebP and the number of its animation frames from its chunks.
+///
+/// The WebP decoder of `image` decodes every frame of an animation as soon as it's created, without any limits, so it
+/// can't be used to check them.
+fn webp_header(bytes: &[u8]) -> Option<(u32, u32, u64)> {
+    let u24 = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    let u14 = |bytes: &[u8]| u16::from_le_bytes([bytes[0], bytes[1]]) as u32 & 0x3fff;
+
+    let mut position = 12;
+    let mut dimensions = None;
+    let mut frames = 0;
+
+    while let Some(header) = bytes.get(position..position + 8) {
+        let length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let data = bytes.get(position + 8..).unwrap_or_default();
+        let data = &data[..length.min(data.len())];
+
+        // The first chunk declares the dimensions, the size of the canvas in the extended format.
+        match (&header[..4], dimensions) {
+            (b"VP8X", None) if data.len() >= 10 => {
+                dimensions = Some((u24(&data[4..]) + 1, u24(&data[7..]) + 1))
+            }
+            (b"VP8 ", None) if data.len() >= 10 => {
+                dimensions = Some((u14(&data[6..]), u14(&data[8..])))
+            }
+            (b"VP8L", None) if data.len() >= 5 => {
+                let bits = u32::from_le_bytes(data[1..5].try_into().unwrap());
+                dimensions = Some(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+            }
+            (_, None) => return None,
+            (b"ANMF", _) => frames += 1,
+            _ => {}
+        }
+
+        // Chunks are padded to an even length.
+        position += 8 + length + (length & 1);
+    }
+
+    dimensions.map(|(width, height)| (width, height, frames))
+}
+
+pub(crate) fn decode_error(format: ImageFormat) -> impl Fn(ImageError) -> ApiError {
+    move |source| match source {
+        ImageError::Limits(err) => {
+            ApiError::ImageTooLarge(format!("The image is too large to be decoded: {err}"))
+        }
+        source => ApiError::Decode {
+            format: format_name(format),
+            source,
+        },
+    }
+}
+
+fn format_name(format: ImageFormat) -> &'static str {
+    use ImageFormat::*;
+
+    match format {
+        Png => "PNG",
+        Jpeg => "JPEG",
+        Gif => "GIF",
+        WebP => "WebP",
+        Bmp => "BMP",
+        Tiff => "TIFF",
+        Ico => "ICO",
+        Avif => "AVIF",
+        Qoi => "QOI",
+        _ => "an image",
+    }
 }
 
 pub fn rgb_to_hex(rgb: &[u8; 3]) -> String {
     format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
 }
+
+/// Formats a byte count for error messages, e.g. `3mb` or `512kb`.
+pub fn format_bytes(bytes: usize) -> String {
+    const KB: usize = 1024;
+    const MB: usize = 1024 * KB;
+
+    if bytes >= MB && bytes.is_multiple_of(MB) {
+        format!("{}mb", bytes / MB)
+    } else if bytes >= KB && bytes.is_multiple_of(KB) {
+        format!("{}kb", bytes / KB)
+    } else {
+        format!("{bytes} bytes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{
+        codecs::webp::{WebPEncoder, WebPQuality},
+        ColorType, ImageBuffer, ImageEncoder, Rgba,
+    };
+
+    const LIMITS: DecodeLimits = DecodeLimits {
+        max_width: 1024,
+        max_height: 1024,
+        max_pixels: 512 * 512,
+        max_alloc: 16 * 1024 * 1024,
+        max_frames: 8,
+        max_animation_pixels: 1024 * 1024,
+    };
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb88320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    /// A PNG that only consists of a header declaring the given dimensions and an empty image stream.
+    fn crafted_png(width: u32, height: u32) -> Bytes {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        // 8 bit RGBA, deflate, no filter, no interlacing
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+        png_chunk(&mut png, b"IHDR", &ihdr);
+        png_chunk(
+            &mut png,
+            b"IDAT",
+            &[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01],
+        );
+        png_chunk(&mut png, b"IEND", &[]);
+
+        png.into()
+    }
+
+    /// A GIF that only consists of a header declaring the given dimensions.
+    fn crafted_gif(width: u16, height: u16) -> Bytes {
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&width.to_le_bytes());
+        gif.extend_from_slice(&height.to_le_bytes());
+        // no global color table
+        gif.extend_from_slice(&[0x00, 0x00, 0x00]);
+        gif.push(0x3b);
+
+        gif.into()
+    }
+
+    /// An animated WebP whose `frames` are `ANMF` chunks of garbage, which can't be decoded.
+    fn crafted_webp(width: u32, height: u32, frames: usize) -> Bytes {
+        let chunk = |kind: &[u8; 4], data: &[u8]| {
+            let mut chunk = kind.to_vec();
+            chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunk.extend_from_slice(data);
+            chunk
+        };
+
+        // the animation flag and the canvas size
+        let mut vp8x = vec![0b10, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+
+        let mut body = b"WEBP".to_vec();
+        body.extend(chunk(b"VP8X", &vp8x));
+        body.extend(chunk(b"ANIM", &[0; 6]));
+        for _ in 0..frames {
+            body.extend(chunk(b"ANMF", &[0xff; 64]));
+        }
+
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        webp.extend(body);
+        webp.into()
+    }
+
+    fn encoded_webp(width: u32, height: u32, quality: WebPQuality, alpha: u8) -> Bytes {
+        let img = ImageBuffer::from_pixel(width, height, Rgba([255u8, 0, 0, alpha]));
+        let mut buffer = Vec::new();
+        WebPEncoder::new_with_quality(&mut buffer, quality)
+            .write_image(&img, width, height, ColorType::Rgba8)
+            .unwrap();
+        buffer.into()
+    }
+
+    fn encoded_png(width: u32, height: u32) -> Bytes {
+        let img = ImageBuffer::from_pixel(width, height, Rgba([255u8, 0, 0, 255]));
+        let mut buffer = Vec::new();
+        img.write_to(&mut Cursor::new(&mut buffer), ImageFormat::Png)
+            .unwrap();
+        buffer.into()
+    }
+
+    #[test]
+    fn decodes_images_within_limits() {
+        let img = decode_with_limits(encoded_png(64, 32), LIMITS).unwrap();
+        assert_eq!(img.dimensions(), (64, 32));
+    }
+
+    #[test]
+    fn rejects_png_bomb_header() {
+        let result = decode_with_limits(crafted_png(50_000, 50_000), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_png_exceeding_width() {
+        let result = decode_with_limits(crafted_png(2048, 1), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_png_exceeding_height() {
+        let result = decode_with_limits(crafted_png(1, 2048), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_png_exceeding_pixel_count() {
+        let result = decode_with_limits(crafted_png(1024, 1024), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_gif_bomb_header() {
+        let result = decode_with_limits(crafted_gif(u16::MAX, u16::MAX), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_allocations_above_limit() {
+        let limits = DecodeLimits {
+            max_alloc: 1024,
+            ..LIMITS
+        };
+        let result = decode_with_limits(encoded_png(256, 256), limits);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn reads_the_dimensions_of_every_webp_kind() {
+        for (quality, alpha) in [
+            (WebPQuality::lossless(), 255),
+            (WebPQuality::lossy(80), 255),
+            (WebPQuality::lossy(80), 128),
+        ] {
+            let webp = encoded_webp(300, 17, quality, alpha);
+
+            assert_eq!(
+                inspect(&webp, LIMITS).unwrap(),
+                (ImageFormat::WebP, (300, 17))
+            );
+            assert_eq!(
+                decode_with_limits(webp, LIMITS).unwrap().dimensions(),
+                (300, 17)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_webp_bomb_header() {
+        let result = decode_with_limits(crafted_webp(16_000, 16_000, 1), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+    }
+
+    #[test]
+    fn rejects_animated_webps_before_decoding_frames() {
+        // the frames are garbage, so decoding them would fail with another error
+        let result = inspect(&crafted_webp(64, 64, 9), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+
+        let result = inspect(&crafted_webp(512, 512, 5), LIMITS);
+        assert!(matches!(result, Err(ApiError::ImageTooLarge(_))));
+
+        let result = decode_with_limits(crafted_webp(64, 64, 8), LIMITS);
+        assert!(matches!(result, Err(ApiError::Decode { .. })));
+    }
+}
@@ -0,0 +1,40 @@
+use crate::error::ApiError;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+
+/// A bounded pool for CPU heavy work like decoding and encoding images, which would otherwise block the async
+/// executor.
+pub struct Workers {
+    permits: Arc<Semaphore>,
+    queue_timeout: Duration,
+}
+
+impl Workers {
+    pub fn new(concurrency: usize, queue_timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(concurrency.max(1))),
+            queue_timeout,
+        }
+    }
+
+    /// Runs `work` on a blocking thread once a worker is free.
+    ///
+    /// Fails with [`ApiError::Busy`] if no worker becomes free within the queue timeout.
+    pub async fn run<F, T>(&self, work: F) -> Result<T, ApiError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned())
+            .await
+            .map_err(|_| ApiError::Busy)?
+            .map_err(|_| ApiError::INTERNAL_SERVER_ERROR)?;
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            work()
+        })
+        .await
+        .map_err(|_| ApiError::INTERNAL_SERVER_ERROR)
+    }
+}